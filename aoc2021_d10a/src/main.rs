@@ -1,84 +1,567 @@
 // AoC 2021 day 10
+//
+//   aoc2021_d10a [--pairs=FILE] [--diagnostics] [--repair]
+//                [--stream [--whitespace=CHARS] [--comment=C]] < input
+// the bracket pairs and their scores come from a table, by default the puzzle's four.  A table
+// file has a line per pair giving the opening and closing characters, the score of finding the
+// closing one where it doesn't belong and its score when completing a line, e.g. "( ) 3 1".
+// Completions are scored in the base one more than the highest completion score (5 for the
+// puzzle).  --diagnostics reports what is wrong with each line, and where
+//
+// --repair writes every line out fixed instead of the answers: incomplete lines get their
+// completion appended and corrupted ones the first single character edit found (replacing or
+// deleting one, trying the corrupted column first and then working back from it) that leaves a
+// line which can be completed.  The edits are reported on stderr
+//
+// --stream reads the whole input as one navigation program instead, so chunks can span lines,
+// and reports whether it is valid, incomplete or where it is corrupted, along with how many
+// chunks open at each depth of nesting and the deepest.  It is read a block at a time so only
+// the open brackets and a count per depth are kept, however large it is.  The characters
+// skipped between brackets are set with --whitespace (\t, \r, \n and \\ can be used, default
+// " \t\r\n") and --comment makes C start a comment running to the end of its line
+//
+// blank lines and lines starting with # are skipped in the table file
+
+// an opening and closing bracket and what they score
+#[derive(Debug)]
+struct Pair {
+    open: char,
+    close: char,
+    error_score: u64,
+    completion_score: u64,
+}
+
+#[derive(Debug)]
+struct Brackets {
+    pairs: Vec<Pair>,
+}
+
+impl Brackets {
+    fn standard() -> Self {
+        let pairs = [
+            ('(', ')', 3, 1),
+            ('[', ']', 57, 2),
+            ('{', '}', 1197, 3),
+            ('<', '>', 25137, 4),
+        ]
+        .iter()
+        .map(|&(open, close, error_score, completion_score)| Pair {
+            open,
+            close,
+            error_score,
+            completion_score,
+        })
+        .collect();
+        Self { pairs }
+    }
+
+    fn opening(&self, c: char) -> Option<usize> {
+        self.pairs.iter().position(|p| p.open == c)
+    }
+
+    fn closing(&self, c: char) -> Option<usize> {
+        self.pairs.iter().position(|p| p.close == c)
+    }
+
+    // score of the closing brackets completing a line, innermost first
+    fn completion_score(&self, completion: &str) -> u64 {
+        let base = self
+            .pairs
+            .iter()
+            .map(|p| p.completion_score)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        completion.chars().fold(0, |score, c| {
+            score * base
+                + self
+                    .closing(c)
+                    .map_or(0, |p| self.pairs[p].completion_score)
+        })
+    }
+}
+
+impl std::str::FromStr for Brackets {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs: Vec<Pair> = vec![];
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bracket = |f: &str| -> Result<char, String> {
+                let mut chars = f.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(c),
+                    _ => Err(format!("{} isn't a single character in {}", f, line)),
+                }
+            };
+            let score = |f: &str| -> Result<u64, String> {
+                f.parse()
+                    .map_err(|_| format!("bad score {} in {}", f, line))
+            };
+            if fields.len() != 4 {
+                return Err(format!(
+                    "expected open close error completion, got {}",
+                    line
+                ));
+            }
+            let pair = Pair {
+                open: bracket(fields[0])?,
+                close: bracket(fields[1])?,
+                error_score: score(fields[2])?,
+                completion_score: score(fields[3])?,
+            };
+            let used = |c: char| pairs.iter().any(|p| p.open == c || p.close == c);
+            if pair.open == pair.close || used(pair.open) || used(pair.close) {
+                return Err(format!("brackets used more than once in {}", line));
+            }
+            pairs.push(pair);
+        }
+        if pairs.is_empty() {
+            return Err("no bracket pairs".to_string());
+        }
+        Ok(Self { pairs })
+    }
+}
+
+// what a line turned out to be, columns count from 1
+#[derive(Debug, PartialEq, Eq)]
+enum Diagnostic {
+    Valid,
+    Incomplete {
+        completion: String,
+    },
+    Corrupted {
+        column: usize,
+        expected: Option<char>, // None if nothing was left open
+        found: char,
+    },
+    BadChar {
+        column: usize,
+        found: char,
+    },
+}
+
+// what feeding a character to the parser did
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Opened,
+    Closed,
+    Corrupted { expected: Option<char> },
+    BadChar,
+}
+
+// the brackets still open, as indices of their pairs
+struct Parser<'a> {
+    brackets: &'a Brackets,
+    stack: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(brackets: &'a Brackets) -> Self {
+        Self {
+            brackets,
+            stack: vec![],
+        }
+    }
+
+    fn feed(&mut self, c: char) -> Step {
+        if let Some(p) = self.brackets.opening(c) {
+            self.stack.push(p);
+            return Step::Opened;
+        }
+        match self.brackets.closing(c) {
+            Some(p) if self.stack.last() == Some(&p) => {
+                self.stack.pop();
+                Step::Closed
+            }
+            Some(_) => Step::Corrupted {
+                expected: self.stack.last().map(|p| self.brackets.pairs[*p].close),
+            },
+            None => Step::BadChar,
+        }
+    }
+
+    // the closing brackets for everything still open
+    fn completion(&self) -> String {
+        self.stack
+            .iter()
+            .rev()
+            .map(|p| self.brackets.pairs[*p].close)
+            .collect()
+    }
+}
+
+fn lint(brackets: &Brackets, line: &str) -> Diagnostic {
+    let mut parser = Parser::new(brackets);
+    for (i, c) in line.chars().enumerate() {
+        match parser.feed(c) {
+            Step::Opened | Step::Closed => {}
+            Step::Corrupted { expected } => {
+                return Diagnostic::Corrupted {
+                    column: i + 1,
+                    expected,
+                    found: c,
+                }
+            }
+            Step::BadChar => {
+                return Diagnostic::BadChar {
+                    column: i + 1,
+                    found: c,
+                }
+            }
+        }
+    }
+    match parser.completion() {
+        c if c.is_empty() => Diagnostic::Valid,
+        completion => Diagnostic::Incomplete { completion },
+    }
+}
+
+// a one character change to a line, columns count from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Replace { column: usize, with: char },
+    Delete { column: usize },
+}
+
+impl Edit {
+    fn apply(&self, line: &str) -> String {
+        let (column, with) = match *self {
+            Edit::Replace { column, with } => (column, Some(with)),
+            Edit::Delete { column } => (column, None),
+        };
+        line.chars()
+            .enumerate()
+            .filter_map(|(i, c)| if i + 1 == column { with } else { Some(c) })
+            .collect()
+    }
+}
+
+// the line fixed, with the edit made to it if it was corrupted, None if no single edit will do
+fn repair(brackets: &Brackets, line: &str) -> Option<(Option<Edit>, String)> {
+    let (column, expected) = match lint(brackets, line) {
+        Diagnostic::Valid => return Some((None, line.to_string())),
+        Diagnostic::Incomplete { completion } => {
+            return Some((None, line.to_string() + &completion))
+        }
+        Diagnostic::Corrupted {
+            column, expected, ..
+        } => (column, expected),
+        Diagnostic::BadChar { column, .. } => (column, None),
+    };
+
+    // nothing after the corrupted column can fix it
+    let mut edits: Vec<Edit> = vec![];
+    if let Some(e) = expected {
+        edits.push(Edit::Replace { column, with: e });
+    }
+    for col in (1..=column).rev() {
+        edits.push(Edit::Delete { column: col });
+        for p in &brackets.pairs {
+            edits.push(Edit::Replace {
+                column: col,
+                with: p.close,
+            });
+        }
+        for p in &brackets.pairs {
+            edits.push(Edit::Replace {
+                column: col,
+                with: p.open,
+            });
+        }
+    }
+
+    edits.into_iter().find_map(|edit| {
+        let edited = edit.apply(line);
+        let fixed = match lint(brackets, &edited) {
+            Diagnostic::Valid => edited,
+            Diagnostic::Incomplete { completion } => edited + &completion,
+            _ => return None,
+        };
+        if lint(brackets, &fixed) != Diagnostic::Valid {
+            panic!("repairing {} gave unbalanced {}", line, fixed);
+        }
+        Some((Some(edit), fixed))
+    })
+}
+
+impl std::fmt::Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Edit::Replace { column, with } => write!(f, "replace column {} with {}", column, with),
+            Edit::Delete { column } => write!(f, "delete column {}", column),
+        }
+    }
+}
+
+// what reading a stream came to, with the line a corrupted column is on
+#[derive(Debug)]
+struct StreamReport {
+    line: usize,
+    diagnostic: Diagnostic,
+    profile: Vec<u64>, // how many chunks opened at each depth, from depth 1
+}
+
+// what is skipped between brackets in a stream
+struct Skipped {
+    whitespace: Vec<char>,
+    comment: Option<char>,
+}
+
+fn parse_stream<R: std::io::Read>(
+    brackets: &Brackets,
+    mut input: R,
+    skipped: &Skipped,
+) -> std::io::Result<StreamReport> {
+    let mut parser = Parser::new(brackets);
+    let mut report = StreamReport {
+        line: 1,
+        diagnostic: Diagnostic::Valid,
+        profile: vec![],
+    };
+    let mut column = 0;
+    let mut in_comment = false;
+
+    let mut buf = vec![0_u8; 1 << 16];
+    let mut carry = 0; // bytes of a character split between reads, at the start of buf
+    loop {
+        let n = match input.read(&mut buf[carry..]) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            if carry != 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "stream ends inside a character",
+                ));
+            }
+            break;
+        }
+        let end = carry + n;
+        let used = match std::str::from_utf8(&buf[..end]) {
+            Ok(_) => end,
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        };
+
+        for c in std::str::from_utf8(&buf[..used]).unwrap().chars() {
+            if c == '\n' {
+                report.line += 1;
+                column = 0;
+                in_comment = false;
+                continue;
+            }
+            column += 1;
+            if in_comment || skipped.whitespace.contains(&c) {
+                continue;
+            }
+            if skipped.comment == Some(c) {
+                in_comment = true;
+                continue;
+            }
+
+            match parser.feed(c) {
+                Step::Opened => {
+                    let depth = parser.stack.len();
+                    if report.profile.len() < depth {
+                        report.profile.push(0);
+                    }
+                    report.profile[depth - 1] += 1;
+                }
+                Step::Closed => {}
+                Step::Corrupted { expected } => {
+                    report.diagnostic = Diagnostic::Corrupted {
+                        column,
+                        expected,
+                        found: c,
+                    };
+                    return Ok(report);
+                }
+                Step::BadChar => {
+                    report.diagnostic = Diagnostic::BadChar { column, found: c };
+                    return Ok(report);
+                }
+            }
+        }
+
+        buf.copy_within(used..end, 0);
+        carry = end - used;
+    }
+
+    let completion = parser.completion();
+    if !completion.is_empty() {
+        report.diagnostic = Diagnostic::Incomplete { completion };
+    }
+    Ok(report)
+}
+
+// the characters of an option, with \t, \r, \n and \\ escapes
+fn unescape(s: &str) -> Vec<char> {
+    let mut chars = vec![];
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            chars.push(c);
+            continue;
+        }
+        chars.push(match iter.next() {
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('n') => '\n',
+            Some('\\') => '\\',
+            other => panic!("bad escape \\{}", other.map_or(String::new(), String::from)),
+        });
+    }
+    chars
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Diagnostic::Valid => write!(f, "valid"),
+            Diagnostic::Incomplete { completion } => {
+                write!(f, "incomplete, complete with {}", completion)
+            }
+            Diagnostic::Corrupted {
+                column,
+                expected: Some(e),
+                found,
+            } => write!(
+                f,
+                "corrupted at column {}, expected {} but found {}",
+                column, e, found
+            ),
+            Diagnostic::Corrupted {
+                column,
+                expected: None,
+                found,
+            } => write!(
+                f,
+                "corrupted at column {}, found {} with nothing open",
+                column, found
+            ),
+            Diagnostic::BadChar { column, found } => {
+                write!(f, "bad character {:?} at column {}", found, column)
+            }
+        }
+    }
+}
 
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
-    let mut err_score: usize = 0;
-    let mut comp_scores: Vec<usize> = vec![];
+    let mut brackets = Brackets::standard();
+    let mut diagnostics = false;
+    let mut repairing = false;
+    let mut streaming = false;
+    let mut skipped = Skipped {
+        whitespace: vec![' ', '\t', '\r', '\n'],
+        comment: None,
+    };
+    let mut err_score: u64 = 0;
+    let mut comp_scores: Vec<u64> = vec![];
+    let mut line_num = 0;
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--diagnostics" {
+            diagnostics = true;
+        } else if arg == "--repair" {
+            repairing = true;
+        } else if arg == "--stream" {
+            streaming = true;
+        } else if let Some(w) = arg.strip_prefix("--whitespace=") {
+            skipped.whitespace = unescape(w);
+        } else if let Some(c) = arg.strip_prefix("--comment=") {
+            match unescape(c)[..] {
+                [c] => skipped.comment = Some(c),
+                _ => panic!("comment must be a single character"),
+            }
+        } else if let Some(path) = arg.strip_prefix("--pairs=") {
+            let text = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("can't read {}: {}", path, e));
+            brackets = text
+                .parse()
+                .unwrap_or_else(|e: String| panic!("{}: {}", path, e));
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
+
+    if streaming {
+        let clash = skipped
+            .whitespace
+            .iter()
+            .chain(&skipped.comment)
+            .find(|c| brackets.opening(**c).is_some() || brackets.closing(**c).is_some());
+        if let Some(c) = clash {
+            panic!("{:?} can't be both a bracket and skipped", c);
+        }
+
+        let report = parse_stream(&brackets, reader.lock(), &skipped)?;
+        match report.diagnostic {
+            Diagnostic::Valid | Diagnostic::Incomplete { .. } => {
+                println!("stream: {}", report.diagnostic)
+            }
+            _ => println!("line {}: {}", report.line, report.diagnostic),
+        }
+        for (d, chunks) in report.profile.iter().enumerate() {
+            println!("depth {}: {} chunks", d + 1, chunks);
+        }
+        println!("max depth: {}", report.profile.len());
+        return Ok(());
+    }
 
     while reader.read_line(&mut inputstr)? != 0 {
-        let mut stack: Vec<char> = vec![];
-        let mut error_found = false;
-        for c in inputstr.trim().chars() {
-            match c {
-                '(' | '[' | '{' | '<' => stack.push(c),
-                ')' => {
-                    if !stack.is_empty() && *stack.last().unwrap() == '(' {
-                        stack.pop();
-                    } else {
-                        err_score += 3;
-                        error_found = true;
-                        break;
-                    }
-                }
-                ']' => {
-                    if !stack.is_empty() && *stack.last().unwrap() == '[' {
-                        stack.pop();
-                    } else {
-                        err_score += 57;
-                        error_found = true;
-                        break;
-                    }
-                }
-                '}' => {
-                    if !stack.is_empty() && *stack.last().unwrap() == '{' {
-                        stack.pop();
-                    } else {
-                        err_score += 1197;
-                        error_found = true;
-                        break;
+        line_num += 1;
+        let input = inputstr.trim();
+        let diagnostic = lint(&brackets, input);
+        if diagnostics {
+            println!("line {}: {}", line_num, diagnostic);
+        }
+        if repairing {
+            match repair(&brackets, input) {
+                Some((edit, fixed)) => {
+                    if let Some(e) = edit {
+                        eprintln!("line {}: {}", line_num, e);
                     }
+                    println!("{}", fixed);
                 }
-                '>' => {
-                    if !stack.is_empty() && *stack.last().unwrap() == '<' {
-                        stack.pop();
-                    } else {
-                        err_score += 25137;
-                        error_found = true;
-                        break;
-                    }
+                None => {
+                    eprintln!("line {}: no single edit repairs it", line_num);
+                    println!("{}", input);
                 }
-                _ => panic!("bad char found {}", c),
             }
         }
 
-        // part two
-        if !error_found {
-            let mut completion_score = 0;
-            while !stack.is_empty() {
-                let s = match stack.pop().unwrap() {
-                    '(' => 1,
-                    '[' => 2,
-                    '{' => 3,
-                    '<' => 4,
-                    _ => {
-                        panic!("bad char")
-                    }
-                };
-                completion_score *= 5;
-                completion_score += s;
+        match &diagnostic {
+            Diagnostic::Corrupted { found, .. } => {
+                err_score += brackets.pairs[brackets.closing(*found).unwrap()].error_score;
             }
-            comp_scores.push(completion_score);
+            // part two
+            Diagnostic::Incomplete { completion } => {
+                comp_scores.push(brackets.completion_score(completion))
+            }
+            Diagnostic::Valid | Diagnostic::BadChar { .. } => {}
         }
 
         inputstr.clear();
     }
 
+    if repairing {
+        return Ok(());
+    }
+
     println!("aoc10a: {}", err_score);
 
     comp_scores.sort_unstable();
     let mid_idx = comp_scores.len() / 2;
-    println!("aoc10b: {}", comp_scores[mid_idx]);
+    println!("aoc10b: {}", comp_scores.get(mid_idx).unwrap_or(&0));
 
     Ok(())
 }
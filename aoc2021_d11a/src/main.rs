@@ -1,79 +1,283 @@
 // AoC 2021 day 11
+//
+// each step the flashes cascade through a work queue: an octopus is queued the moment its energy
+// goes over the threshold and flashes once when it comes off the queue, so the grid is scanned
+// once per step rather than until nothing new flashes
+//
+//   aoc2021_d11a [--history[=N]] [--wrap] [--threshold=T] [--gain=G] [--cycle]
+//                [--max-steps=N] < input
+// --history lists the octopuses (as x,y) that flashed at each of the first N steps, by default
+// every step run (until they first all flash together, or 100 steps if that comes later)
+//
+// --wrap joins opposite edges of the grid so every octopus has eight neighbors (fewer on a grid
+// less than 3 wide or high, where wrapping reaches the same ones again), --threshold sets the
+// energy an octopus has to go over to flash (9 by default) and --gain how much energy each gets
+// every step (1 by default)
+//
+// there are only so many states the grid can be in, so it always ends up going round a cycle.
+// The cycle is found with Brent's algorithm, stepping copies of the grid rather than keeping
+// every state, and if the octopuses haven't all flashed together by the time the grid has been
+// round it once they never will.  --cycle reports the step the cycle starts at and its period,
+// and --max-steps (1000000 by default) limits how far to look for it
 
-// increment all locations, return true if they were all zeros prior to incrementing them
-fn increment_all(v: &mut [Vec<u8>]) -> bool {
-    let mut all_zeroes = true;
-    for r in 0..v.len() {
-        for c in 0..v[r].len() {
-            v[r][c] += 1;
+use std::collections::VecDeque;
 
-            if v[r][c] > 1 {
-                all_zeroes = false;
+// the octopuses' energy levels, row by row, and how they behave
+#[derive(Clone)]
+struct Cavern {
+    width: usize,
+    height: usize,
+    energy: Vec<u32>,
+    wrap: bool,     // whether opposite edges are joined
+    threshold: u32, // an octopus flashes when its energy goes over this
+    gain: u32,      // energy gained every step
+}
+
+// the grid repeats states from start on, every period steps
+#[derive(Debug)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+// the octopuses that flashed at each step, in the order they did
+struct History {
+    steps: Vec<Vec<usize>>,
+}
+
+impl Cavern {
+    fn xy(&self, i: usize) -> (usize, usize) {
+        (i % self.width, i / self.width)
+    }
+
+    // indices of the (up to) eight neighbors of octopus i, each once
+    fn neighbors(&self, i: usize) -> Vec<usize> {
+        let (x, y) = self.xy(i);
+        let mut around: Vec<usize> = (-1..=1_isize)
+            .flat_map(|dy| (-1..=1_isize).map(move |dx| (dx, dy)))
+            .filter(|d| *d != (0, 0))
+            .filter_map(move |(dx, dy)| {
+                if self.wrap {
+                    let xx = (x as isize + dx).rem_euclid(self.width as isize) as usize;
+                    let yy = (y as isize + dy).rem_euclid(self.height as isize) as usize;
+                    return Some(yy * self.width + xx);
+                }
+                let xx = x.checked_add_signed(dx)?;
+                let yy = y.checked_add_signed(dy)?;
+                if xx < self.width && yy < self.height {
+                    Some(yy * self.width + xx)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        // wrapping round a grid less than 3 wide or high reaches the same octopus (or i itself)
+        // by more than one way
+        if self.wrap {
+            around.sort_unstable();
+            around.dedup();
+            around.retain(|n| *n != i);
+        }
+        around
+    }
+
+    // run one step, returning the octopuses that flashed in the order they did
+    fn step(&mut self) -> Vec<usize> {
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for (i, e) in self.energy.iter_mut().enumerate() {
+            *e += self.gain;
+            if *e > self.threshold {
+                queue.push_back(i);
+            }
+        }
+
+        let mut flashed = vec![];
+        while let Some(i) = queue.pop_front() {
+            flashed.push(i);
+            for n in self.neighbors(i) {
+                // octopuses already over the threshold were queued when they went over it
+                self.energy[n] += 1;
+                if self.energy[n] == self.threshold + 1 {
+                    queue.push_back(n);
+                }
             }
         }
+
+        for i in &flashed {
+            self.energy[*i] = 0;
+        }
+        flashed
     }
 
-    all_zeroes
+    // find the cycle the grid ends up in (by Brent's algorithm), if it is within max_steps
+    fn find_cycle(&self, max_steps: usize) -> Option<Cycle> {
+        // find the period by moving the tortoise up to the hare at powers of two
+        let mut tortoise = self.clone();
+        let mut hare = self.clone();
+        hare.step();
+        let (mut power, mut period, mut steps) = (1, 1, 1);
+        while tortoise.energy != hare.energy {
+            if steps >= max_steps {
+                return None;
+            }
+            if power == period {
+                tortoise.energy.clone_from(&hare.energy);
+                power *= 2;
+                period = 0;
+            }
+            hare.step();
+            period += 1;
+            steps += 1;
+        }
+
+        // the first state repeated is where a tortoise and a hare a period ahead first meet
+        let mut tortoise = self.clone();
+        let mut hare = self.clone();
+        for _ in 0..period {
+            hare.step();
+        }
+        let mut start = 0;
+        while tortoise.energy != hare.energy {
+            tortoise.step();
+            hare.step();
+            start += 1;
+        }
+
+        Some(Cycle { start, period })
+    }
 }
 
-// make a pass through all octopuses counting the number that flash
-fn flash(v: &mut [Vec<u8>]) -> usize {
-    let mut retval = 0;
-    for r in 0..v.len() {
-        for c in 0..v[r].len() {
-            if v[r][c] > 9 {
-                retval += 1; // count the flash
-                v[r][c] = 0;
-
-                // propagate the flash
-                let llr = r.saturating_sub(1);
-                let ulr = if r < v.len() - 1 { r + 1 } else { r };
-                let llc = c.saturating_sub(1);
-                let ulc = if c < v[r].len() - 1 { c + 1 } else { c };
-                for rr in llr..=ulr {
-                    for cc in llc..=ulc {
-                        if v[rr][cc] != 0 {
-                            v[rr][cc] += 1;
-                        }
-                    }
-                }
+impl History {
+    // run some more steps
+    fn run(&mut self, cavern: &mut Cavern, steps: usize) {
+        self.steps.extend((0..steps).map(|_| cavern.step()));
+    }
+
+    // run until every octopus flashes at once, or until max_steps have been recorded
+    fn run_until_sync(&mut self, cavern: &mut Cavern, max_steps: usize) {
+        while self.steps.len() < max_steps {
+            let flashed = cavern.step();
+            let synced = flashed.len() == cavern.energy.len();
+            self.steps.push(flashed);
+            if synced {
+                break;
             }
         }
     }
 
-    retval
+    fn flash_counts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.steps.iter().map(|f| f.len())
+    }
+
+    // flashes during the first steps
+    fn total_flashes(&self, steps: usize) -> usize {
+        self.flash_counts().take(steps).sum()
+    }
+
+    // the first step (counting from 1) when all cells flashed
+    fn first_sync(&self, cells: usize) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|f| f.len() == cells)
+            .map(|s| s + 1)
+    }
 }
 
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
-    let mut vals: Vec<Vec<u8>> = vec![];
+    let mut cavern = Cavern {
+        width: 0,
+        height: 0,
+        energy: vec![],
+        wrap: false,
+        threshold: 9,
+        gain: 1,
+    };
+    let mut show_history: Option<usize> = None;
+    let mut show_cycle = false;
+    let mut max_steps = 1_000_000;
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--history" {
+            show_history = Some(usize::MAX);
+        } else if let Some(n) = arg.strip_prefix("--history=") {
+            show_history = Some(n.parse().expect("bad step count"));
+        } else if arg == "--wrap" {
+            cavern.wrap = true;
+        } else if arg == "--cycle" {
+            show_cycle = true;
+        } else if let Some(t) = arg.strip_prefix("--threshold=") {
+            cavern.threshold = t.parse().expect("bad threshold");
+        } else if let Some(g) = arg.strip_prefix("--gain=") {
+            cavern.gain = g.parse().expect("bad energy gain");
+        } else if let Some(n) = arg.strip_prefix("--max-steps=") {
+            max_steps = n.parse().expect("bad step count");
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
 
     while reader.read_line(&mut inputstr)? != 0 {
-        let mut val: Vec<u8> = vec![];
-        for c in inputstr.trim_end().chars() {
-            val.push(c as u8 - b'0');
+        let input = inputstr.trim_end();
+        if !input.is_empty() {
+            let row: Vec<u32> = input
+                .chars()
+                .map(|c| c.to_digit(10).expect("bad energy level"))
+                .collect();
+            if cavern.height == 0 {
+                cavern.width = row.len();
+            } else if row.len() != cavern.width {
+                panic!("row {} isn't {} wide", cavern.height + 1, cavern.width);
+            }
+            cavern.energy.extend(row);
+            cavern.height += 1;
         }
-        vals.push(val);
 
         inputstr.clear();
     }
 
-    let mut total_flashes = 0;
-    for step in 0.. {
-        if step == 100 {
-            println!("aoc11a: {}", total_flashes);
-        }
+    let cells = cavern.energy.len();
+    let cycle = cavern.find_cycle(max_steps);
+
+    // a sync has to happen before the grid has been round the cycle once
+    let sync_limit = cycle.as_ref().map_or(max_steps, |c| c.start + c.period);
+    let mut history = History { steps: vec![] };
+    history.run_until_sync(&mut cavern, sync_limit);
+    if history.steps.len() < 100 {
+        history.run(&mut cavern, 100 - history.steps.len());
+    }
 
-        if increment_all(&mut vals) {
-            println!("aoc11b: {}", step);
-            break;
+    println!("aoc11a: {}", history.total_flashes(100));
+    match (history.first_sync(cells), &cycle) {
+        (Some(step), _) => println!("aoc11b: {}", step),
+        (None, Some(_)) => println!("aoc11b: never"),
+        (None, None) => println!("aoc11b: not within {} steps", max_steps),
+    }
+    if show_cycle {
+        match &cycle {
+            Some(c) => println!("cycle: starts at step {} period {}", c.start, c.period),
+            None => println!("cycle: not within {} steps", max_steps),
         }
+    }
 
-        let mut new_flashes = flash(&mut vals);
-        while new_flashes > 0 {
-            total_flashes += new_flashes;
-            new_flashes = flash(&mut vals);
+    if let Some(n) = show_history {
+        for (step, (flashed, count)) in history
+            .steps
+            .iter()
+            .zip(history.flash_counts())
+            .enumerate()
+            .take(n)
+        {
+            let cells: Vec<String> = flashed
+                .iter()
+                .map(|i| {
+                    let (x, y) = cavern.xy(*i);
+                    format!("{},{}", x, y)
+                })
+                .collect();
+            println!("step {}: {} flashes {}", step + 1, count, cells.join(" "));
         }
     }
 
@@ -1,102 +1,559 @@
 // AOC 2021 day 3
+//
+// the report is stored column-major: each column is a packed bitset over the report rows.
+// this makes the width of the report arbitrary, lets part one count ones with popcounts and
+// lets part two narrow the candidate rows with word-wide ANDs instead of removing them one by one
 
-fn sort_oxy_co2(vals: &[Vec<u8>], v: &mut Vec<usize>, target: u8, idx: usize) {
-    if v.len() > 1 {
-        let mut to_remove: Vec<usize> = vec![];
-        for (ii, i) in v.iter().enumerate() {
-            if vals[*i][idx] != target {
-                to_remove.push(ii);
+// packed bitset of len bits, bit i is stored in word i / 64
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    // bitset of len bits with every bit set
+    fn full(len: usize) -> Self {
+        let mut bs = Self {
+            words: vec![u64::MAX; len.div_ceil(64)],
+            len,
+        };
+        bs.clear_tail();
+        bs
+    }
+
+    // make sure the unused bits of the last word are zero so that popcounts stay correct
+    fn clear_tail(&mut self) {
+        if !self.len.is_multiple_of(64) {
+            if let Some(w) = self.words.last_mut() {
+                *w &= (1 << (self.len % 64)) - 1;
             }
         }
+    }
 
-        while !to_remove.is_empty() {
-            v.remove(to_remove.pop().unwrap());
+    fn get(&self, i: usize) -> bool {
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    // grow the bitset by one bit
+    fn push(&mut self, b: bool) {
+        if self.len.is_multiple_of(64) {
+            self.words.push(0);
+        }
+        self.len += 1;
+        if b {
+            self.set(self.len - 1);
         }
     }
+
+    fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    // number of bits set in both self and other
+    fn count_and(&self, other: &BitSet) -> usize {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    // keep only the bits that are also set in other
+    fn and_assign(&mut self, other: &BitSet) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= b;
+        }
+    }
+
+    // keep only the bits that are not set in other
+    fn and_not_assign(&mut self, other: &BitSet) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= !b;
+        }
+    }
+
+    // index of the first set bit, if any
+    fn first_one(&self) -> Option<usize> {
+        self.words
+            .iter()
+            .enumerate()
+            .find(|(_, w)| **w != 0)
+            .map(|(i, w)| i * 64 + w.trailing_zeros() as usize)
+    }
+
+    // the value of the bitset when bit 0 is taken as the most significant bit, None if it won't fit
+    fn to_u128(&self) -> Option<u128> {
+        if self.len > 128 {
+            return None;
+        }
+        Some((0..self.len).fold(0, |s, i| s * 2 + self.get(i) as u128))
+    }
+
+    fn to_binary_string(&self) -> String {
+        (0..self.len)
+            .map(|i| if self.get(i) { '1' } else { '0' })
+            .collect()
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    let reader = std::io::stdin();
-    let mut inputstr = String::new();
-    let mut v: Vec<usize> = vec![0; 20];
-    let mut vals: Vec<Vec<u8>> = vec![];
-    let mut num_lines = 0;
+// which bit a rating keeps when one bit value is more common than the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Commonality {
+    Most,
+    Least,
+}
 
-    while reader.read_line(&mut inputstr)? != 0 {
-        if num_lines == 0 {
-            v.truncate(inputstr.trim_end().len());
+// order in which the columns of the report are considered by a rating
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+// bit criteria of a life support style rating
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BitCriteria {
+    keep: Commonality,
+    tie: bool, // the bit that is kept when ones and zeros are equally common
+    order: BitOrder,
+}
+
+impl BitCriteria {
+    const OXYGEN: BitCriteria = BitCriteria {
+        keep: Commonality::Most,
+        tie: true,
+        order: BitOrder::MsbFirst,
+    };
+    const CO2: BitCriteria = BitCriteria {
+        keep: Commonality::Least,
+        tie: false,
+        order: BitOrder::MsbFirst,
+    };
+
+    // returns true if the rows with a one should be kept given the counts of the candidates
+    fn keep_ones(&self, count1: usize, count0: usize) -> bool {
+        if count1 == count0 {
+            self.tie
+        } else {
+            (count1 > count0) == (self.keep == Commonality::Most)
+        }
+    }
+}
+
+// parses criteria of the form "most|least[:tie bit][:msb|lsb]", e.g. "least:0:lsb"
+impl std::str::FromStr for BitCriteria {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.split(':');
+        let keep = match iter.next() {
+            Some("most") => Commonality::Most,
+            Some("least") => Commonality::Least,
+            _ => return Err(format!("bad criteria {}, expected most or least", s)),
+        };
+        let tie = match iter.next() {
+            None => keep == Commonality::Most,
+            Some("1") => true,
+            Some("0") => false,
+            Some(t) => return Err(format!("bad tie bit {}", t)),
+        };
+        let order = match iter.next() {
+            None | Some("msb") => BitOrder::MsbFirst,
+            Some("lsb") => BitOrder::LsbFirst,
+            Some(o) => return Err(format!("bad bit order {}", o)),
+        };
+        if iter.next().is_some() {
+            return Err(format!("bad criteria {}", s));
         }
 
-        let mut val: Vec<u8> = vec![];
-        for (i, c) in inputstr.trim_end().chars().enumerate() {
+        Ok(Self { keep, tie, order })
+    }
+}
+
+// what happened to the candidates at one column of a rating
+#[derive(Debug, Clone, Copy)]
+struct Elimination {
+    column: usize,
+    kept_bit: bool,
+    before: usize, // number of candidates before this column
+    eliminated: usize,
+}
+
+// the outcome of filtering the report with a BitCriteria
+#[derive(Debug)]
+struct Rating {
+    value: BitSet,
+    row: usize,
+    trace: Vec<Elimination>,
+}
+
+// diagnostic report of any width
+#[derive(Debug)]
+struct Report {
+    columns: Vec<BitSet>, // columns[c] has bit r set if row r has a 1 in column c
+    num_rows: usize,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            columns: vec![],
+            num_rows: 0,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.columns.len()
+    }
+
+    // add one line of the report, the first line determines the width of the report
+    fn push_row(&mut self, s: &str) {
+        if self.num_rows == 0 {
+            self.columns = vec![BitSet::new(0); s.len()];
+        } else if s.len() != self.width() {
+            panic!(
+                "line {} has width {}, expected {}",
+                s,
+                s.len(),
+                self.width()
+            );
+        }
+
+        for (col, c) in self.columns.iter_mut().zip(s.chars()) {
             match c {
-                '1' => v[i] += 1,
-                '0' => {}
+                '1' => col.push(true),
+                '0' => col.push(false),
                 _ => panic!("bad character {}", c),
             }
-            val.push(c as u8 - b'0');
         }
-        vals.push(val);
-
-        num_lines += 1;
-        inputstr.clear();
+        self.num_rows += 1;
     }
 
-    // part one
-    let mut gamma = 0;
-    let mut epsilon = 0;
-
-    for count1 in &v {
-        gamma <<= 1;
-        epsilon <<= 1;
-        if count1 >= &(num_lines - count1) {
-            gamma += 1;
-        } else {
-            epsilon += 1;
+    // the value of a row, column 0 is the most significant bit
+    fn row_value(&self, row: usize) -> BitSet {
+        let mut bs = BitSet::new(self.width());
+        for (i, col) in self.columns.iter().enumerate() {
+            if col.get(row) {
+                bs.set(i);
+            }
         }
+        bs
     }
-    println!("aoc3a: {}", gamma * epsilon);
 
-    // part two
-    let mut oxygen_idx: Vec<usize> = (0..num_lines).collect();
-    let mut co2_idx: Vec<usize> = (0..num_lines).collect();
-
-    for i in 0..v.len() {
-        let mut count1 = 0;
-        for j in &oxygen_idx {
-            if vals[*j][i] == 1 {
-                count1 += 1;
+    // gamma takes the most common bit of each column (ties go to 1), epsilon is its complement
+    fn gamma_epsilon(&self) -> (BitSet, BitSet) {
+        let mut gamma = BitSet::new(self.width());
+        let mut epsilon = BitSet::new(self.width());
+        for (i, col) in self.columns.iter().enumerate() {
+            let count1 = col.count_ones();
+            if count1 >= self.num_rows - count1 {
+                gamma.set(i);
+            } else {
+                epsilon.set(i);
             }
         }
+        (gamma, epsilon)
+    }
 
-        if count1 >= oxygen_idx.len() - count1 {
-            sort_oxy_co2(&vals, &mut oxygen_idx, 1, i);
-        } else {
-            sort_oxy_co2(&vals, &mut oxygen_idx, 0, i);
-        }
+    // filter the rows column by column until one is left, keeping the rows whose bit matches
+    // the criteria.  The surviving value is returned along with the elimination trace
+    fn filter_rating(&self, criteria: &BitCriteria) -> Rating {
+        let mut candidates = BitSet::full(self.num_rows);
+        let mut remaining = self.num_rows;
+        let mut trace: Vec<Elimination> = vec![];
 
-        count1 = 0;
-        for j in &co2_idx {
-            if vals[*j][i] == 1 {
-                count1 += 1;
+        let order: Vec<usize> = match criteria.order {
+            BitOrder::MsbFirst => (0..self.width()).collect(),
+            BitOrder::LsbFirst => (0..self.width()).rev().collect(),
+        };
+
+        for column in order {
+            if remaining <= 1 {
+                break;
             }
+
+            let col = &self.columns[column];
+            let count1 = candidates.count_and(col);
+            let keep_ones = if count1 == 0 || count1 == remaining {
+                count1 != 0 // every candidate has the same bit here, nothing to eliminate
+            } else {
+                criteria.keep_ones(count1, remaining - count1)
+            };
+
+            let kept = if keep_ones {
+                candidates.and_assign(col);
+                count1
+            } else {
+                candidates.and_not_assign(col);
+                remaining - count1
+            };
+            trace.push(Elimination {
+                column,
+                kept_bit: keep_ones,
+                before: remaining,
+                eliminated: remaining - kept,
+            });
+            remaining = kept;
         }
 
-        if count1 >= co2_idx.len() - count1 {
-            sort_oxy_co2(&vals, &mut co2_idx, 0, i);
+        let row = candidates
+            .first_one()
+            .expect("no rows left after filtering");
+        Rating {
+            value: self.row_value(row),
+            row,
+            trace,
+        }
+    }
+}
+
+// per column statistics of the report
+#[derive(Debug)]
+struct ColumnStats {
+    column: usize,
+    ones: usize,
+    zeros: usize,
+    gamma_bit: bool, // the epsilon bit is its complement
+    oxygen_eliminated: usize,
+    co2_eliminated: usize,
+}
+
+impl ColumnStats {
+    fn ratio(&self) -> f64 {
+        if self.ones + self.zeros == 0 {
+            0.0
         } else {
-            sort_oxy_co2(&vals, &mut co2_idx, 1, i);
+            self.ones as f64 / (self.ones + self.zeros) as f64
         }
     }
+}
 
-    let oxy_val = vals[oxygen_idx[0]]
+// build the per column statistics from the report and its oxygen and co2 ratings
+fn column_stats(report: &Report, gamma: &BitSet, oxy: &Rating, co2: &Rating) -> Vec<ColumnStats> {
+    let mut stats: Vec<ColumnStats> = report
+        .columns
         .iter()
-        .fold(0, |s: usize, x| s * 2 + *x as usize);
-    let co2_val = vals[co2_idx[0]]
+        .enumerate()
+        .map(|(column, col)| {
+            let ones = col.count_ones();
+            ColumnStats {
+                column,
+                ones,
+                zeros: report.num_rows - ones,
+                gamma_bit: gamma.get(column),
+                oxygen_eliminated: 0,
+                co2_eliminated: 0,
+            }
+        })
+        .collect();
+
+    for e in &oxy.trace {
+        stats[e.column].oxygen_eliminated = e.eliminated;
+    }
+    for e in &co2.trace {
+        stats[e.column].co2_eliminated = e.eliminated;
+    }
+
+    stats
+}
+
+// how the results are written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    Answers, // just the puzzle answers
+    Table,   // the answers preceded by a per column table
+    Json,    // everything as a single json object
+}
+
+// the product of two report values, None if it won't fit in a u128
+fn product(a: &BitSet, b: &BitSet) -> Option<u128> {
+    a.to_u128()?.checked_mul(b.to_u128()?)
+}
+
+// print the product of two report values, falling back to their binary form if it gets too big
+fn print_product(label: &str, a: &BitSet, b: &BitSet) {
+    match product(a, b) {
+        Some(p) => println!("{}: {}", label, p),
+        None => println!(
+            "{}: {} * {}",
+            label,
+            a.to_binary_string(),
+            b.to_binary_string()
+        ),
+    }
+}
+
+fn print_table(stats: &[ColumnStats]) {
+    println!(
+        "{:>6} {:>8} {:>8} {:>6} {:>5} {:>7} {:>6} {:>6}",
+        "column", "ones", "zeros", "ratio", "gamma", "epsilon", "oxy", "co2"
+    );
+    for cs in stats {
+        println!(
+            "{:>6} {:>8} {:>8} {:>6.3} {:>5} {:>7} {:>6} {:>6}",
+            cs.column,
+            cs.ones,
+            cs.zeros,
+            cs.ratio(),
+            cs.gamma_bit as u8,
+            !cs.gamma_bit as u8,
+            cs.oxygen_eliminated,
+            cs.co2_eliminated
+        );
+    }
+}
+
+fn print_rating(label: &str, rating: &Rating) {
+    println!(
+        "rating {}: {} (row {})",
+        label,
+        rating.value.to_binary_string(),
+        rating.row
+    );
+    for e in &rating.trace {
+        println!(
+            "  column {}: kept {}, {} -> {} candidates",
+            e.column,
+            e.kept_bit as u8,
+            e.before,
+            e.before - e.eliminated
+        );
+    }
+}
+
+fn json_product(a: &BitSet, b: &BitSet) -> String {
+    product(a, b).map_or("null".to_string(), |p| p.to_string())
+}
+
+fn json_rating(label: &str, rating: &Rating) -> String {
+    let trace: Vec<String> = rating
+        .trace
         .iter()
-        .fold(0, |s: usize, x| s * 2 + *x as usize);
+        .map(|e| {
+            format!(
+                "{{\"column\":{},\"kept_bit\":{},\"before\":{},\"eliminated\":{}}}",
+                e.column, e.kept_bit as u8, e.before, e.eliminated
+            )
+        })
+        .collect();
+    format!(
+        "{{\"criteria\":\"{}\",\"value\":\"{}\",\"row\":{},\"trace\":[{}]}}",
+        label,
+        rating.value.to_binary_string(),
+        rating.row,
+        trace.join(",")
+    )
+}
+
+fn print_json(
+    report: &Report,
+    stats: &[ColumnStats],
+    (gamma, epsilon): (&BitSet, &BitSet),
+    (oxy, co2): (&Rating, &Rating),
+    ratings: &[(String, Rating)],
+) {
+    let columns: Vec<String> = stats
+        .iter()
+        .map(|cs| {
+            format!(
+                "{{\"column\":{},\"ones\":{},\"zeros\":{},\"ratio\":{:.6},\"gamma\":{},\"epsilon\":{},\"oxygen_eliminated\":{},\"co2_eliminated\":{}}}",
+                cs.column,
+                cs.ones,
+                cs.zeros,
+                cs.ratio(),
+                cs.gamma_bit as u8,
+                !cs.gamma_bit as u8,
+                cs.oxygen_eliminated,
+                cs.co2_eliminated
+            )
+        })
+        .collect();
+    let ratings: Vec<String> = ratings.iter().map(|(l, r)| json_rating(l, r)).collect();
+
+    println!("{{");
+    println!("  \"width\": {},", report.width());
+    println!("  \"rows\": {},", report.num_rows);
+    println!("  \"gamma\": \"{}\",", gamma.to_binary_string());
+    println!("  \"epsilon\": \"{}\",", epsilon.to_binary_string());
+    println!("  \"power_consumption\": {},", json_product(gamma, epsilon));
+    println!("  \"oxygen\": {},", json_rating("oxygen", oxy));
+    println!("  \"co2\": {},", json_rating("co2", co2));
+    println!(
+        "  \"life_support\": {},",
+        json_product(&oxy.value, &co2.value)
+    );
+    println!("  \"columns\": [\n    {}\n  ],", columns.join(",\n    "));
+    println!("  \"ratings\": [{}]", ratings.join(","));
+    println!("}}");
+}
+
+// usage: aoc2021_d3a [--table | --json] [criteria ...] < input
+fn main() -> std::io::Result<()> {
+    let reader = std::io::stdin();
+    let mut inputstr = String::new();
+    let mut report = Report::new();
+    let mut mode = OutputMode::Answers;
+    let mut criteria: Vec<(String, BitCriteria)> = vec![];
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--table" => mode = OutputMode::Table,
+            "--json" => mode = OutputMode::Json,
+            _ => {
+                let c: BitCriteria = arg.parse().unwrap_or_else(|e: String| panic!("{}", e));
+                criteria.push((arg, c));
+            }
+        }
+    }
+
+    while reader.read_line(&mut inputstr)? != 0 {
+        let input = inputstr.trim();
+        if !input.is_empty() {
+            report.push_row(input);
+        }
+
+        inputstr.clear();
+    }
+
+    // part one
+    let (gamma, epsilon) = report.gamma_epsilon();
+
+    // part two
+    let oxy = report.filter_rating(&BitCriteria::OXYGEN);
+    let co2 = report.filter_rating(&BitCriteria::CO2);
+
+    // any further ratings passed on the command line, along with how they were reached
+    let ratings: Vec<(String, Rating)> = criteria
+        .into_iter()
+        .map(|(label, c)| (label, report.filter_rating(&c)))
+        .collect();
 
-    println!("aoc3b: {}", oxy_val * co2_val);
+    let stats = column_stats(&report, &gamma, &oxy, &co2);
+    match mode {
+        OutputMode::Json => print_json(&report, &stats, (&gamma, &epsilon), (&oxy, &co2), &ratings),
+        OutputMode::Answers | OutputMode::Table => {
+            if mode == OutputMode::Table {
+                print_table(&stats);
+            }
+            print_product("aoc3a", &gamma, &epsilon);
+            print_product("aoc3b", &oxy.value, &co2.value);
+            for (label, rating) in &ratings {
+                print_rating(label, rating);
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1,44 +1,424 @@
-use std::collections::HashSet;
+// AoC 2021 day 4
+//
+// cards may be any size (their size is inferred from the input) and the ways of winning can be
+// chosen on the command line:
+//   aoc2021_d4a [--diagonals] [--patterns=row,column,diagonal,corners,blackout,x]
+//               [--ranking] [--rank=N] [--replay] [--monte-carlo=TRIALS [--seed=S]] < input
+//
+// the whole game is played out so every card ends up with its place in the win order:
+//   --ranking lists every card in the order it won, --rank=N shows the card that won Nth along
+//   with its marked state and --replay lists the cards that won on each draw
+//
+// --monte-carlo=TRIALS replays the game with the caller's numbers shuffled TRIALS times (seeded
+// with --seed=S) and estimates how likely each card is to win first or last
 
+use std::collections::HashMap;
+
+// a way of winning a game of bingo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WinPattern {
+    Row,         // any full row
+    Column,      // any full column
+    Diagonal,    // either full diagonal, square cards only
+    FourCorners, // the four corner cells
+    Blackout,    // every cell of the card
+    XShape,      // both diagonals, square cards only
+}
+
+impl std::str::FromStr for WinPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "row" => Ok(WinPattern::Row),
+            "column" => Ok(WinPattern::Column),
+            "diagonal" => Ok(WinPattern::Diagonal),
+            "corners" => Ok(WinPattern::FourCorners),
+            "blackout" => Ok(WinPattern::Blackout),
+            "x" => Ok(WinPattern::XShape),
+            _ => Err(format!("unknown win pattern {}", s)),
+        }
+    }
+}
+
+// a card along with running counts of its marked cells so that wins can be checked in O(1)
 #[derive(Debug)]
 struct BingoCard {
     card: Vec<Vec<(u32, bool)>>,
+    row_hits: Vec<usize>, // number of marked cells in each row
+    col_hits: Vec<usize>, // number of marked cells in each column
+    diag_hits: usize,     // marked cells on the top left to bottom right diagonal
+    anti_hits: usize,     // marked cells on the top right to bottom left diagonal
+    corner_hits: usize,
+    total_hits: usize,
+    unmarked_sum: u32,
 }
 
 impl BingoCard {
     fn new() -> Self {
-        Self { card: vec![] }
+        Self {
+            card: vec![],
+            row_hits: vec![],
+            col_hits: vec![],
+            diag_hits: 0,
+            anti_hits: 0,
+            corner_hits: 0,
+            total_hits: 0,
+            unmarked_sum: 0,
+        }
+    }
+
+    fn height(&self) -> usize {
+        self.card.len()
+    }
+
+    fn width(&self) -> usize {
+        self.card.first().map_or(0, |r| r.len())
+    }
+
+    // add a row of numbers to the card, all rows must be the same length
+    fn push_row(&mut self, input: &str) {
+        let row: Vec<(u32, bool)> = input
+            .split_whitespace()
+            .map(|s| (s.parse::<u32>().expect("bad bingo number"), false))
+            .collect();
+        if self.card.is_empty() {
+            self.col_hits = vec![0; row.len()];
+        } else if row.len() != self.width() {
+            panic!(
+                "card row {} has {} numbers, expected {}",
+                input,
+                row.len(),
+                self.width()
+            );
+        }
+        self.unmarked_sum += row.iter().map(|(n, _)| n).sum::<u32>();
+        self.row_hits.push(0);
+        self.card.push(row);
+    }
+
+    // number of distinct corner cells, less than 4 for cards with a single row or column
+    fn num_corners(&self) -> usize {
+        (if self.height() > 1 { 2 } else { 1 }) * (if self.width() > 1 { 2 } else { 1 })
+    }
+
+    fn is_corner(&self, y: usize, x: usize) -> bool {
+        (y == 0 || y == self.height() - 1) && (x == 0 || x == self.width() - 1)
+    }
+
+    // returns true if the card has the passed pattern given that (y, x) was just marked
+    fn has_pattern(&self, pattern: WinPattern, y: usize, x: usize) -> bool {
+        let (h, w) = (self.height(), self.width());
+        match pattern {
+            WinPattern::Row => self.row_hits[y] == w,
+            WinPattern::Column => self.col_hits[x] == h,
+            WinPattern::Diagonal => {
+                h == w
+                    && ((x == y && self.diag_hits == h) || (x + y == w - 1 && self.anti_hits == h))
+            }
+            WinPattern::FourCorners => self.corner_hits == self.num_corners(),
+            WinPattern::Blackout => self.total_hits == h * w,
+            WinPattern::XShape => h == w && self.diag_hits == h && self.anti_hits == h,
+        }
     }
 
-    // function to mark the board with the passed number.  If the mark results in a bingo then true is returned
-    fn mark_and_check(&mut self, num: u32) -> bool {
-        let mut ccopy = self.card.clone();
-        for (y, v) in self.card.iter_mut().enumerate() {
-            for (x, (n, m)) in v.iter_mut().enumerate() {
-                if *n == num {
-                    *m = true; // mark
-                    ccopy[y][x].1 = true; // mark clone as well
+    // mark the cell at (y, x).  If the mark results in one of the passed win patterns then true
+    // is returned
+    fn mark(&mut self, y: usize, x: usize, patterns: &[WinPattern]) -> bool {
+        if self.card[y][x].1 {
+            return false; // already marked
+        }
 
-                    // if the marked row or column is full return true otherwise return false
-                    return (0..5).map(|xx| ccopy[y][xx].1).all(|b| b)
-                        || (0..5).map(|yy| ccopy[yy][x].1).all(|b| b);
+        self.card[y][x].1 = true;
+        self.unmarked_sum -= self.card[y][x].0;
+        self.row_hits[y] += 1;
+        self.col_hits[x] += 1;
+        self.total_hits += 1;
+        if self.height() == self.width() {
+            if x == y {
+                self.diag_hits += 1;
+            }
+            if x + y == self.width() - 1 {
+                self.anti_hits += 1;
+            }
+        }
+        if self.is_corner(y, x) {
+            self.corner_hits += 1;
+        }
+
+        patterns.iter().any(|p| self.has_pattern(*p, y, x))
+    }
+
+    // clear every mark so the card can be played again
+    fn reset(&mut self) {
+        for row in self.card.iter_mut() {
+            for (_, m) in row.iter_mut() {
+                *m = false;
+            }
+        }
+        self.row_hits.iter_mut().for_each(|h| *h = 0);
+        self.col_hits.iter_mut().for_each(|h| *h = 0);
+        self.diag_hits = 0;
+        self.anti_hits = 0;
+        self.corner_hits = 0;
+        self.total_hits = 0;
+        self.unmarked_sum = self.card.iter().flatten().map(|(n, _)| n).sum();
+    }
+
+    fn marked(&self) -> Vec<Vec<bool>> {
+        self.card
+            .iter()
+            .map(|r| r.iter().map(|(_, m)| *m).collect())
+            .collect()
+    }
+}
+
+// lookup from a number to every cell it appears in, so a draw only touches the cells it marks
+#[derive(Debug)]
+struct BingoIndex {
+    cells: HashMap<u32, Vec<(usize, usize, usize)>>, // (card, row, column)
+}
+
+impl BingoIndex {
+    fn new(cards: &[BingoCard]) -> Self {
+        let mut cells: HashMap<u32, Vec<(usize, usize, usize)>> = HashMap::new();
+        for (idx, bc) in cards.iter().enumerate() {
+            for (y, row) in bc.card.iter().enumerate() {
+                for (x, (n, _)) in row.iter().enumerate() {
+                    cells.entry(*n).or_default().push((idx, y, x));
                 }
             }
         }
+        Self { cells }
+    }
+
+    fn cells_of(&self, num: u32) -> &[(usize, usize, usize)] {
+        self.cells.get(&num).map_or(&[], |v| v.as_slice())
+    }
+}
 
-        false // num not found on card
+// the moment a card won
+#[derive(Debug, Clone)]
+struct Win {
+    draw: usize, // index into the caller's numbers
+    number: u32,
+    score: u32,
+    marked: Vec<Vec<bool>>, // the marked state of the card when it won
+}
+
+// how a card fared over the whole game
+#[derive(Debug, Clone)]
+struct CardResult {
+    card: usize,      // index of the card in the input
+    win: Option<Win>, // None if the card never won
+}
+
+// play every number of the caller, returning the result of every card in the order they won.
+// Cards that won on the same draw keep their input order and cards that never won come last
+fn play(
+    cards: &mut [BingoCard],
+    index: &BingoIndex,
+    caller: &[u32],
+    patterns: &[WinPattern],
+) -> Vec<CardResult> {
+    let mut wins: Vec<Option<Win>> = vec![None; cards.len()];
+    let mut num_won = 0;
+
+    for (draw, n) in caller.iter().enumerate() {
+        for &(idx, y, x) in index.cells_of(*n) {
+            let bc = &mut cards[idx];
+            if wins[idx].is_none() && bc.mark(y, x, patterns) {
+                wins[idx] = Some(Win {
+                    draw,
+                    number: *n,
+                    score: bc.unmarked_sum * n,
+                    marked: bc.marked(),
+                });
+                num_won += 1;
+            }
+        }
+
+        if num_won == cards.len() {
+            break;
+        }
     }
 
-    fn calc_sum_of_unmarked(&self) -> u32 {
-        let mut sum = 0;
-        for y in 0..5 {
-            for x in 0..5 {
-                if !self.card[y][x].1 {
-                    sum += self.card[y][x].0;
+    let mut results: Vec<CardResult> = wins
+        .into_iter()
+        .enumerate()
+        .map(|(card, win)| CardResult { card, win })
+        .collect();
+    results.sort_by_key(|r| r.win.as_ref().map_or(usize::MAX, |w| w.draw));
+    results
+}
+
+fn print_ranking(results: &[CardResult]) {
+    println!(
+        "{:>5} {:>6} {:>6} {:>6} {:>8}",
+        "rank", "card", "draw", "number", "score"
+    );
+    for (rank, r) in results.iter().enumerate() {
+        match &r.win {
+            Some(w) => println!(
+                "{:>5} {:>6} {:>6} {:>6} {:>8}",
+                rank + 1,
+                r.card,
+                w.draw,
+                w.number,
+                w.score
+            ),
+            None => println!("{:>5} {:>6} {:>6}", "-", r.card, "never"),
+        }
+    }
+}
+
+// print the card as it was when it won, marked numbers are starred
+fn print_rank(results: &[CardResult], cards: &[BingoCard], rank: usize) {
+    match results
+        .get(rank.wrapping_sub(1))
+        .and_then(|r| r.win.as_ref().map(|w| (r, w)))
+    {
+        Some((r, w)) => {
+            println!(
+                "rank {}: card {} won on draw {} ({}) with score {}",
+                rank, r.card, w.draw, w.number, w.score
+            );
+            for (row, marks) in cards[r.card].card.iter().zip(&w.marked) {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(marks)
+                    .map(|((n, _), m)| format!("{:>3}{}", n, if *m { '*' } else { ' ' }))
+                    .collect();
+                println!("{}", line.join(""));
+            }
+        }
+        None => println!("rank {}: no card won in that place", rank),
+    }
+}
+
+fn print_replay(results: &[CardResult], caller: &[u32]) {
+    let mut iter = results
+        .iter()
+        .filter_map(|r| r.win.as_ref().map(|w| (r.card, w)))
+        .peekable();
+    for (draw, n) in caller.iter().enumerate() {
+        let mut winners: Vec<String> = vec![];
+        while let Some((card, _)) = iter.next_if(|(_, w)| w.draw == draw) {
+            winners.push(card.to_string());
+        }
+        if !winners.is_empty() {
+            println!("draw {} ({}): card {} won", draw, n, winners.join(", "));
+        }
+    }
+}
+
+// small seeded pseudo random number generator (splitmix64)
+#[derive(Debug)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform value in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Fisher-Yates shuffle
+    fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+// how a card did over many shuffled games
+#[derive(Debug, Clone, Default)]
+struct CardOdds {
+    first: usize, // games in which the card was among the first winners
+    last: usize,  // games in which the card was among the last winners
+    wins: usize,  // games in which the card won at all
+    draw_sum: usize,
+}
+
+// play trials games with the caller's numbers in a random order, tallying how each card did.
+// Cards that win on the same draw share the first (or last) place
+fn monte_carlo(
+    cards: &mut [BingoCard],
+    index: &BingoIndex,
+    caller: &[u32],
+    patterns: &[WinPattern],
+    trials: usize,
+    seed: u64,
+) -> Vec<CardOdds> {
+    let mut rng = Rng::new(seed);
+    let mut order = caller.to_vec();
+    let mut odds = vec![CardOdds::default(); cards.len()];
+
+    for _ in 0..trials {
+        rng.shuffle(&mut order);
+        cards.iter_mut().for_each(|bc| bc.reset());
+
+        let results = play(cards, index, &order, patterns);
+        let draws: Vec<usize> = results
+            .iter()
+            .filter_map(|r| r.win.as_ref().map(|w| w.draw))
+            .collect();
+        let (first_draw, last_draw) = match (draws.first(), draws.last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => continue, // nobody won this game
+        };
+
+        for r in &results {
+            if let Some(w) = &r.win {
+                let o = &mut odds[r.card];
+                o.wins += 1;
+                o.draw_sum += w.draw;
+                if w.draw == first_draw {
+                    o.first += 1;
+                }
+                if w.draw == last_draw {
+                    o.last += 1;
                 }
             }
         }
-        sum
+    }
+
+    odds
+}
+
+fn print_odds(odds: &[CardOdds], trials: usize) {
+    println!(
+        "{:>6} {:>8} {:>8} {:>8} {:>10}",
+        "card", "p_first", "p_last", "p_win", "mean_draw"
+    );
+    let t = trials.max(1) as f64;
+    for (card, o) in odds.iter().enumerate() {
+        let mean_draw = if o.wins == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.2}", o.draw_sum as f64 / o.wins as f64)
+        };
+        println!(
+            "{:>6} {:>8.4} {:>8.4} {:>8.4} {:>10}",
+            card,
+            o.first as f64 / t,
+            o.last as f64 / t,
+            o.wins as f64 / t,
+            mean_draw
+        );
     }
 }
 
@@ -48,7 +428,36 @@ fn main() -> std::io::Result<()> {
     let mut first_line = true;
     let mut caller: Vec<u32> = vec![];
     let mut cards: Vec<BingoCard> = vec![];
-    let mut card_idx = 0;
+    let mut in_card = false;
+    let mut patterns = vec![WinPattern::Row, WinPattern::Column];
+    let mut show_ranking = false;
+    let mut show_replay = false;
+    let mut show_rank: Option<usize> = None;
+    let mut trials: Option<usize> = None;
+    let mut seed = 2021;
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--diagonals" {
+            patterns.push(WinPattern::Diagonal);
+        } else if let Some(list) = arg.strip_prefix("--patterns=") {
+            patterns = list
+                .split(',')
+                .map(|s| s.parse().unwrap_or_else(|e: String| panic!("{}", e)))
+                .collect();
+        } else if arg == "--ranking" {
+            show_ranking = true;
+        } else if arg == "--replay" {
+            show_replay = true;
+        } else if let Some(rank) = arg.strip_prefix("--rank=") {
+            show_rank = Some(rank.parse::<usize>().expect("bad rank"));
+        } else if let Some(t) = arg.strip_prefix("--monte-carlo=") {
+            trials = Some(t.parse::<usize>().expect("bad number of trials"));
+        } else if let Some(sd) = arg.strip_prefix("--seed=") {
+            seed = sd.parse::<u64>().expect("bad seed");
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
 
     while reader.read_line(&mut inputstr)? != 0 {
         if first_line {
@@ -59,42 +468,44 @@ fn main() -> std::io::Result<()> {
                 .collect();
             first_line = false;
         } else {
+            // cards are separated by blank lines, their size comes from the rows they contain
             let input = inputstr.trim();
             if input.is_empty() {
-                cards.push(BingoCard::new());
+                in_card = false;
             } else {
-                cards[card_idx].card.push(
-                    input
-                        .split(' ')
-                        .filter(|s| !s.is_empty())
-                        .map(|s| (s.parse::<u32>().unwrap(), false))
-                        .collect(),
-                );
-                if cards[card_idx].card.len() == 5 {
-                    card_idx += 1;
+                if !in_card {
+                    cards.push(BingoCard::new());
+                    in_card = true;
                 }
+                cards.last_mut().unwrap().push_row(input);
             }
         }
 
         inputstr.clear();
     }
 
-    let mut winner_indices: HashSet<usize> = HashSet::new();
+    let index = BingoIndex::new(&cards);
+    let results = play(&mut cards, &index, &caller, &patterns);
+    let winners: Vec<&Win> = results.iter().filter_map(|r| r.win.as_ref()).collect();
 
-    'lp: for n in &caller {
-        for (idx, bc) in cards.iter_mut().enumerate() {
-            if winner_indices.contains(&idx) {
-                continue;
-            } else if bc.mark_and_check(*n) {
-                if winner_indices.is_empty() {
-                    println!("aoc4a: {}", bc.calc_sum_of_unmarked() * n);
-                } else if winner_indices.len() == card_idx - 1 {
-                    println!("aoc4b: {}", bc.calc_sum_of_unmarked() * n);
-                    break 'lp;
-                }
-                winner_indices.insert(idx);
-            }
-        }
+    // with some patterns not every card is guaranteed to win, report on the last one that did
+    if let (Some(first), Some(last)) = (winners.first(), winners.last()) {
+        println!("aoc4a: {}", first.score);
+        println!("aoc4b: {}", last.score);
+    }
+
+    if show_ranking {
+        print_ranking(&results);
+    }
+    if let Some(rank) = show_rank {
+        print_rank(&results, &cards, rank);
+    }
+    if show_replay {
+        print_replay(&results, &caller);
+    }
+    if let Some(trials) = trials {
+        let odds = monte_carlo(&mut cards, &index, &caller, &patterns, trials, seed);
+        print_odds(&odds, trials);
     }
 
     Ok(())
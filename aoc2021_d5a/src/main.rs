@@ -1,87 +1,1036 @@
 // AoC 2021 day 5
+//
+// lines of any slope are handled in one of two ways, chosen on the command line:
+//   aoc2021_d5a [--lattice | --touched] < input
+//   --lattice (the default) only covers the points with integer coordinates on the line
+//   --touched covers every cell the line passes through, each point being the center of a
+//   unit cell.  A line passing exactly through the corner of a cell doesn't touch it
+// for horizontal, vertical and 45 degree lines both give the same points
+//
+// lattice overlaps are counted without visiting every covered point:
+//   segments on the same infinite line are grouped and the stretches of that line covered by
+//   two or more of them are found by sweeping the segments' start and end events along it
+//   segments on different lines meet in at most one point, the candidate pairs are found by
+//   sweeping over x with the set of segments whose x range is still active
+//   crossing points already inside one or more collinear overlaps are only counted once
+// --touched still rasterizes every line into a map of covered cells
+//
+// the overlap map of all the lines can also be rendered, cropped to the bounding box of the
+// lines or to --crop=xmin,ymin,xmax,ymax:
+//   --ascii prints it like the puzzle does, --pgm=FILE writes it as a grayscale image and
+//   --ppm=FILE as a color image, in both images brighter means more overlapping lines
+//
+// and the map can be queried:
+//   --at=x,y lists the lines passing through a point, only checking the lines whose x range
+//   holds it, as found by an interval tree over the x ranges
+//   --cells=xmin,ymin,xmax,ymax[:k] lists the cells of a rectangle covered by at least k lines
+//   (2 if not given, and at least 1).  The lines are found by their x ranges overlapping the
+//   rectangle and only the cells they cover are counted, so the rectangle can be any size
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Point {
-    x: u16,
-    y: u16,
+    x: i64,
+    y: i64,
 }
 
 impl Point {
     fn new(s: &str) -> Self {
         let mut iter = s.split(',');
-        let x = iter.next().unwrap().parse::<u16>().unwrap();
-        let y = iter.next().unwrap().parse::<u16>().unwrap();
+        let x = iter.next().unwrap().trim().parse::<i64>().unwrap();
+        let y = iter.next().unwrap().trim().parse::<i64>().unwrap();
         Self { x, y }
     }
 }
 
+// how a line is turned into points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Raster {
+    Lattice,
+    Touched,
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// function that returns the points covered by the line from p1 to p2
+fn rasterize(p1: &Point, p2: &Point, raster: Raster) -> Vec<Point> {
+    let (x1, y1) = (p1.x, p1.y);
+    let dx = p2.x as i128 - x1 as i128;
+    let dy = p2.y as i128 - y1 as i128;
+
+    match raster {
+        Raster::Lattice => {
+            // step from lattice point to lattice point
+            let g = gcd(dx.abs(), dy.abs());
+            if g == 0 {
+                return vec![*p1];
+            }
+            (0..=g)
+                .map(|i| Point {
+                    x: (x1 as i128 + i * (dx / g)) as i64,
+                    y: (y1 as i128 + i * (dy / g)) as i64,
+                })
+                .collect()
+        }
+        Raster::Touched => {
+            // walk the grid, at each step crossing whichever cell boundary comes first
+            let (nx, ny) = (dx.abs(), dy.abs());
+            let (sx, sy) = (dx.signum() as i64, dy.signum() as i64);
+            let (mut x, mut y) = (x1, y1);
+            let (mut ix, mut iy) = (0, 0);
+            let mut retval = vec![Point { x, y }];
+            while ix < nx || iy < ny {
+                let decision = (1 + 2 * ix) * ny - (1 + 2 * iy) * nx;
+                if decision == 0 {
+                    // through a corner, go diagonally
+                    x += sx;
+                    y += sy;
+                    ix += 1;
+                    iy += 1;
+                } else if decision < 0 {
+                    x += sx;
+                    ix += 1;
+                } else {
+                    y += sy;
+                    iy += 1;
+                }
+                retval.push(Point { x, y });
+            }
+            retval
+        }
+    }
+}
+
+// a line segment walked in lattice steps of (a, b) from start, the direction is normalized so
+// that a > 0 or a == 0 and b > 0, a single point is taken to be horizontal
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: Point,
+    a: i128,
+    b: i128,
+    steps: i128,
+}
+
+impl Segment {
+    fn new(p1: &Point, p2: &Point) -> Self {
+        let (dx, dy) = (p2.x as i128 - p1.x as i128, p2.y as i128 - p1.y as i128);
+        let g = gcd(dx.abs(), dy.abs());
+        if g == 0 {
+            return Self {
+                start: *p1,
+                a: 1,
+                b: 0,
+                steps: 0,
+            };
+        }
+
+        let (a, b) = (dx / g, dy / g);
+        if a > 0 || (a == 0 && b > 0) {
+            Self {
+                start: *p1,
+                a,
+                b,
+                steps: g,
+            }
+        } else {
+            Self {
+                start: *p2,
+                a: -a,
+                b: -b,
+                steps: g,
+            }
+        }
+    }
+
+    // identifies the infinite line the segment lies on by its direction and its lattice point
+    // at step index 0
+    fn line_key(&self) -> (i128, i128, i128, i128) {
+        let t = self.index(&self.start);
+        let (x, y) = (self.start.x as i128, self.start.y as i128);
+        (self.a, self.b, x - t * self.a, y - t * self.b)
+    }
+
+    // step index of a lattice point of the line, consecutive lattice points differ by 1.  All
+    // of them have the same x mod a, so x div a counts the steps from the one with 0 <= x < a
+    fn index(&self, p: &Point) -> i128 {
+        if self.a != 0 {
+            (p.x as i128).div_euclid(self.a)
+        } else {
+            p.y as i128
+        }
+    }
+
+    // the lattice point t steps from the start
+    fn at(&self, t: i128) -> Point {
+        Point {
+            x: (self.start.x as i128 + t * self.a) as i64,
+            y: (self.start.y as i128 + t * self.b) as i64,
+        }
+    }
+
+    fn end(&self) -> Point {
+        self.at(self.steps)
+    }
+
+    fn x_range(&self) -> (i64, i64) {
+        let e = self.end();
+        (self.start.x.min(e.x), self.start.x.max(e.x))
+    }
+
+    fn y_range(&self) -> (i64, i64) {
+        let e = self.end();
+        (self.start.y.min(e.y), self.start.y.max(e.y))
+    }
+
+    fn contains(&self, p: &Point) -> bool {
+        let (wx, wy) = (
+            p.x as i128 - self.start.x as i128,
+            p.y as i128 - self.start.y as i128,
+        );
+        let t = if self.a != 0 {
+            if wx % self.a != 0 {
+                return false;
+            }
+            wx / self.a
+        } else {
+            if wx != 0 {
+                return false;
+            }
+            wy
+        };
+        // t * b is within the segment's y range before it can overflow
+        (0..=self.steps).contains(&t) && wy == t * self.b
+    }
+
+    // which side of the line p is on
+    fn side(&self, p: &Point) -> std::cmp::Ordering {
+        let (wx, wy) = (
+            p.x as i128 - self.start.x as i128,
+            p.y as i128 - self.start.y as i128,
+        );
+        cmp_products(self.a, wy, self.b, wx)
+    }
+
+    // the lattice point where two segments on different lines cross, if there is one
+    fn crossing(&self, other: &Segment) -> Option<Point> {
+        use std::cmp::Ordering::Equal;
+        if cmp_products(self.a, other.b, self.b, other.a) == Equal {
+            return None; // parallel
+        }
+
+        // the side of the other line changes monotonically along this segment, so binary
+        // search its steps for the point on it
+        let (first, last) = (other.side(&self.start), other.side(&self.end()));
+        let t = if first == Equal {
+            0
+        } else if last == Equal {
+            self.steps
+        } else if first == last {
+            return None;
+        } else {
+            let (mut lo, mut hi) = (0, self.steps);
+            loop {
+                if hi - lo <= 1 {
+                    return None; // the lines cross between lattice points
+                }
+                let mid = lo + (hi - lo) / 2;
+                match other.side(&self.at(mid)) {
+                    Equal => break mid,
+                    s if s == first => lo = mid,
+                    _ => hi = mid,
+                }
+            }
+        };
+
+        let p = self.at(t);
+        if other.contains(&p) {
+            Some(p)
+        } else {
+            None
+        }
+    }
+
+    // the range of steps of the segment inside the box, None if it misses it
+    fn clipped(&self, bbox: &BBox) -> Option<(i128, i128)> {
+        let tx = clip(
+            self.start.x as i128,
+            self.a,
+            bbox.xmin as i128,
+            bbox.xmax as i128,
+        )?;
+        let ty = clip(
+            self.start.y as i128,
+            self.b,
+            bbox.ymin as i128,
+            bbox.ymax as i128,
+        )?;
+        let tlo = tx.0.max(ty.0).max(0);
+        let thi = tx.1.min(ty.1).min(self.steps);
+        if tlo <= thi {
+            Some((tlo, thi))
+        } else {
+            None
+        }
+    }
+}
+
+// compares a * b with c * d, all of them smaller than 2^64 in size so the products fit in 128
+// bits unsigned
+fn cmp_products(a: i128, b: i128, c: i128, d: i128) -> std::cmp::Ordering {
+    let signed = |x: i128, y: i128| (x.signum() * y.signum(), x.unsigned_abs() * y.unsigned_abs());
+    let ((s1, m1), (s2, m2)) = (signed(a, b), signed(c, d));
+    match s1.cmp(&s2) {
+        std::cmp::Ordering::Equal if s1 < 0 => m2.cmp(&m1),
+        std::cmp::Ordering::Equal => m1.cmp(&m2),
+        o => o,
+    }
+}
+
+// the stretches of one infinite line covered by two or more segments
+#[derive(Debug)]
+struct LineOverlaps {
+    ranges: Vec<(i128, i128)>, // sorted, disjoint, inclusive ranges of step indices
+}
+
+impl LineOverlaps {
+    // sweep the start and end events of the segments along the line
+    fn new(segments: &[&Segment]) -> Self {
+        let mut events: Vec<(i128, i32)> = vec![];
+        for s in segments {
+            let t = s.index(&s.start);
+            events.push((t, 1));
+            events.push((t + s.steps + 1, -1)); // coverage ends after the last point
+        }
+        events.sort_unstable();
+
+        let mut ranges: Vec<(i128, i128)> = vec![];
+        let mut coverage = 0;
+        let mut from = 0;
+        for (t, delta) in events {
+            let was = coverage;
+            coverage += delta;
+            if was < 2 && coverage >= 2 {
+                from = t;
+            } else if was >= 2 && coverage < 2 && t > from {
+                ranges.push((from, t - 1));
+            }
+        }
+
+        Self { ranges }
+    }
+
+    fn num_points(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(lo, hi)| (hi - lo + 1) as usize)
+            .sum()
+    }
+
+    fn contains(&self, t: i128) -> bool {
+        let idx = self.ranges.partition_point(|(_, hi)| *hi < t);
+        idx < self.ranges.len() && self.ranges[idx].0 <= t
+    }
+}
+
+// count the lattice points covered by at least two of the segments
+fn count_overlaps(segments: &[Segment]) -> usize {
+    // collinear overlaps
+    let mut groups: HashMap<(i128, i128, i128, i128), Vec<&Segment>> = HashMap::new();
+    for s in segments {
+        groups.entry(s.line_key()).or_default().push(s);
+    }
+    let overlaps: HashMap<(i128, i128, i128, i128), LineOverlaps> = groups
+        .iter()
+        .map(|(k, v)| (*k, LineOverlaps::new(v)))
+        .collect();
+    let mut total: usize = overlaps.values().map(|lo| lo.num_points()).sum();
+
+    // crossings, sweeping over x.  Each crossing point remembers the lines it was found on
+    let mut order: Vec<&Segment> = segments.iter().collect();
+    order.sort_unstable_by_key(|s| s.x_range().0);
+    let mut active: Vec<&Segment> = vec![];
+    let mut crossings: HashMap<Point, Vec<(i128, i128, i128, i128)>> = HashMap::new();
+    for s in order {
+        let (xlo, _) = s.x_range();
+        active.retain(|o| o.x_range().1 >= xlo);
+
+        let (ylo, yhi) = s.y_range();
+        for o in &active {
+            let (oylo, oyhi) = o.y_range();
+            if oyhi < ylo || oylo > yhi || o.line_key() == s.line_key() {
+                continue;
+            }
+            if let Some(p) = s.crossing(o) {
+                let lines = crossings.entry(p).or_default();
+                lines.push(s.line_key());
+                lines.push(o.line_key());
+            }
+        }
+        active.push(s);
+    }
+
+    // a crossing point is already counted once for every line it lies on in an overlap
+    for (p, lines) in crossings.iter_mut() {
+        lines.sort_unstable();
+        lines.dedup();
+        let counted = lines
+            .iter()
+            .filter(|k| overlaps[*k].contains(groups[*k][0].index(p)))
+            .count();
+        if counted == 0 {
+            total += 1;
+        } else {
+            total -= counted - 1;
+        }
+    }
+
+    total
+}
+
+// inclusive rectangle of the vent map
+#[derive(Debug, Clone, Copy)]
+struct BBox {
+    xmin: i64,
+    ymin: i64,
+    xmax: i64,
+    ymax: i64,
+}
+
+impl BBox {
+    // the smallest box holding all the lines
+    fn of_lines(lines: &[(Point, Point)]) -> Self {
+        let pts = lines.iter().flat_map(|l| [l.0, l.1]);
+        Self {
+            xmin: pts.clone().map(|p| p.x).min().unwrap_or(0),
+            ymin: pts.clone().map(|p| p.y).min().unwrap_or(0),
+            xmax: pts.clone().map(|p| p.x).max().unwrap_or(0),
+            ymax: pts.map(|p| p.y).max().unwrap_or(0),
+        }
+    }
+
+    // the number of columns, None if it doesn't fit in a usize
+    fn width(&self) -> Option<usize> {
+        usize::try_from(self.xmax as i128 - self.xmin as i128 + 1).ok()
+    }
+
+    // the number of rows, None if it doesn't fit in a usize
+    fn height(&self) -> Option<usize> {
+        usize::try_from(self.ymax as i128 - self.ymin as i128 + 1).ok()
+    }
+
+    fn contains(&self, p: &Point) -> bool {
+        (self.xmin..=self.xmax).contains(&p.x) && (self.ymin..=self.ymax).contains(&p.y)
+    }
+}
+
+impl std::str::FromStr for BBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v: Vec<i64> = s
+            .split(',')
+            .map(|n| n.trim().parse::<i64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("bad crop {}: {}", s, e))?;
+        match v[..] {
+            [xmin, ymin, xmax, ymax] if xmin <= xmax && ymin <= ymax => Ok(Self {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            }),
+            _ => Err(format!("bad crop {}, expected xmin,ymin,xmax,ymax", s)),
+        }
+    }
+}
+
+// the range of t for which lo <= s + t * d <= hi, None if there isn't one
+fn clip(s: i128, d: i128, lo: i128, hi: i128) -> Option<(i128, i128)> {
+    if d == 0 {
+        return if lo <= s && s <= hi {
+            Some((i128::MIN, i128::MAX))
+        } else {
+            None
+        };
+    }
+
+    let (s, d, lo, hi) = if d < 0 {
+        (-s, -d, -hi, -lo)
+    } else {
+        (s, d, lo, hi)
+    };
+    let tlo = -(s - lo).div_euclid(d);
+    let thi = (hi - s).div_euclid(d);
+    if tlo <= thi {
+        Some((tlo, thi))
+    } else {
+        None
+    }
+}
+
+// the number of lines covering each cell of the box, indexed [y][x] from its top left corner
+fn render_counts(lines: &[(Point, Point)], raster: Raster, bbox: &BBox) -> Vec<Vec<u32>> {
+    let (width, height) = match (bbox.width(), bbox.height()) {
+        (Some(w), Some(h)) if w.checked_mul(h).is_some_and(|n| n <= 100_000_000) => (w, h),
+        _ => panic!("the map is too big to render, crop it to a smaller box"),
+    };
+
+    let mut counts = vec![vec![0_u32; width]; height];
+    let mut count = |p: &Point| counts[(p.y - bbox.ymin) as usize][(p.x - bbox.xmin) as usize] += 1;
+    for l in lines {
+        match raster {
+            Raster::Lattice => {
+                // only visit the points of the line inside the box
+                let s = Segment::new(&l.0, &l.1);
+                if let Some((tlo, thi)) = s.clipped(bbox) {
+                    (tlo..=thi).for_each(|t| count(&s.at(t)));
+                }
+            }
+            Raster::Touched => rasterize(&l.0, &l.1, raster)
+                .iter()
+                .filter(|p| bbox.contains(p))
+                .for_each(&mut count),
+        }
+    }
+
+    counts
+}
+
+// the map as drawn in the puzzle, counts over 9 are shown as +
+fn print_ascii(counts: &[Vec<u32>]) {
+    for row in counts {
+        let line: String = row
+            .iter()
+            .map(|c| match c {
+                0 => '.',
+                1..=9 => (b'0' + *c as u8) as char,
+                _ => '+',
+            })
+            .collect();
+        println!("{}", line);
+    }
+}
+
+// color for a count relative to the largest count, going from black through blue and red to
+// yellow
+fn heat_color(c: u32, max: u32) -> [u8; 3] {
+    if c == 0 {
+        return [0, 0, 0];
+    }
+    let f = c as f64 / max.max(1) as f64;
+    let ramp = |lo: f64, hi: f64| (((f - lo) / (hi - lo)).clamp(0.0, 1.0) * 255.0) as u8;
+    [ramp(0.0, 0.5), ramp(0.5, 1.0), 255 - ramp(0.0, 0.5)]
+}
+
+// write the map as a binary pgm (grayscale) or ppm (color) image
+fn write_image(path: &str, counts: &[Vec<u32>], color: bool) -> std::io::Result<()> {
+    let max = counts.iter().flatten().copied().max().unwrap_or(0);
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let (h, w) = (counts.len(), counts.first().map_or(0, |r| r.len()));
+    writeln!(out, "{}\n{} {}\n255", if color { "P6" } else { "P5" }, w, h)?;
+
+    for c in counts.iter().flatten() {
+        if color {
+            out.write_all(&heat_color(*c, max))?;
+        } else {
+            out.write_all(&[(*c as u64 * 255 / max.max(1) as u64) as u8])?;
+        }
+    }
+
+    out.flush()
+}
+
+// a centered interval tree: the ranges holding the center are kept at the node, sorted both by
+// their low and by their high ends, and the ones entirely below or above it go to the children
+#[derive(Debug)]
+struct IntervalTree {
+    center: i64,
+    by_lo: Vec<(i64, usize)>, // (low end, index) ascending
+    by_hi: Vec<(i64, usize)>, // (high end, index) descending
+    below: Option<Box<IntervalTree>>,
+    above: Option<Box<IntervalTree>>,
+}
+
+impl IntervalTree {
+    // build from inclusive ranges (lo, hi) tagged with an index
+    fn build(ranges: Vec<(i64, i64, usize)>) -> Option<Box<Self>> {
+        if ranges.is_empty() {
+            return None;
+        }
+
+        // the median end always lies in some range, so every node keeps at least one
+        let mut ends: Vec<i64> = ranges.iter().flat_map(|r| [r.0, r.1]).collect();
+        let mid = ends.len() / 2;
+        let center = *ends.select_nth_unstable(mid).1;
+
+        let (mut below, mut above, mut here) = (vec![], vec![], vec![]);
+        for r in ranges {
+            if r.1 < center {
+                below.push(r);
+            } else if r.0 > center {
+                above.push(r);
+            } else {
+                here.push(r);
+            }
+        }
+
+        let mut by_lo: Vec<(i64, usize)> = here.iter().map(|r| (r.0, r.2)).collect();
+        by_lo.sort_unstable();
+        let mut by_hi: Vec<(i64, usize)> = here.iter().map(|r| (r.1, r.2)).collect();
+        by_hi.sort_unstable_by(|a, b| b.cmp(a));
+
+        Some(Box::new(Self {
+            center,
+            by_lo,
+            by_hi,
+            below: Self::build(below),
+            above: Self::build(above),
+        }))
+    }
+
+    // the indices of the ranges holding x
+    fn stab(&self, x: i64, found: &mut Vec<usize>) {
+        let mut node = Some(self);
+        while let Some(n) = node {
+            if x < n.center {
+                found.extend(n.by_lo.iter().take_while(|r| r.0 <= x).map(|r| r.1));
+                node = n.below.as_deref();
+            } else if x > n.center {
+                found.extend(n.by_hi.iter().take_while(|r| r.0 >= x).map(|r| r.1));
+                node = n.above.as_deref();
+            } else {
+                found.extend(n.by_lo.iter().map(|r| r.1));
+                node = None;
+            }
+        }
+    }
+
+    // the indices of the ranges overlapping lo..=hi
+    fn overlapping(&self, lo: i64, hi: i64, found: &mut Vec<usize>) {
+        let mut stack = vec![self];
+        while let Some(n) = stack.pop() {
+            if hi < n.center {
+                found.extend(n.by_lo.iter().take_while(|r| r.0 <= hi).map(|r| r.1));
+            } else if lo > n.center {
+                found.extend(n.by_hi.iter().take_while(|r| r.0 >= lo).map(|r| r.1));
+            } else {
+                found.extend(n.by_lo.iter().map(|r| r.1));
+            }
+            if lo < n.center {
+                stack.extend(n.below.as_deref());
+            }
+            if hi > n.center {
+                stack.extend(n.above.as_deref());
+            }
+        }
+    }
+}
+
+// the parsed lines along with the way they are rasterized and an index of their x ranges, for
+// answering queries about the map
+#[derive(Debug)]
+struct VentMap<'a> {
+    lines: &'a [(Point, Point)],
+    segments: Vec<Segment>,
+    raster: Raster,
+    by_x: Option<Box<IntervalTree>>,
+}
+
+impl<'a> VentMap<'a> {
+    fn new(lines: &'a [(Point, Point)], raster: Raster) -> Self {
+        let segments: Vec<Segment> = lines.iter().map(|l| Segment::new(&l.0, &l.1)).collect();
+        let ranges = segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let (lo, hi) = s.x_range();
+                (lo, hi, i)
+            })
+            .collect();
+        Self {
+            lines,
+            segments,
+            raster,
+            by_x: IntervalTree::build(ranges),
+        }
+    }
+
+    // indices of the input lines passing through p
+    fn lines_through(&self, p: &Point) -> Vec<usize> {
+        let mut candidates = vec![];
+        if let Some(t) = &self.by_x {
+            t.stab(p.x, &mut candidates);
+        }
+        candidates.sort_unstable();
+
+        candidates
+            .into_iter()
+            .filter(|i| {
+                let s = &self.segments[*i];
+                let (ylo, yhi) = s.y_range();
+                match self.raster {
+                    Raster::Lattice => s.contains(p),
+                    Raster::Touched => {
+                        // a touched cell is never outside the bounding box of the line
+                        (ylo..=yhi).contains(&p.y)
+                            && rasterize(&self.lines[*i].0, &self.lines[*i].1, self.raster)
+                                .contains(p)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // every cell of the box covered by at least k (> 0) lines along with its count, in reading
+    // order.  Only the covered cells are counted, so the box can be any size
+    fn cells_at_least(&self, bbox: &BBox, k: u32) -> Vec<(Point, u32)> {
+        let mut candidates = vec![];
+        if let Some(t) = &self.by_x {
+            t.overlapping(bbox.xmin, bbox.xmax, &mut candidates);
+        }
+
+        let mut counts: HashMap<Point, u32> = HashMap::new();
+        for i in candidates {
+            match self.raster {
+                Raster::Lattice => {
+                    let s = &self.segments[i];
+                    if let Some((tlo, thi)) = s.clipped(bbox) {
+                        for t in tlo..=thi {
+                            *counts.entry(s.at(t)).or_default() += 1;
+                        }
+                    }
+                }
+                Raster::Touched => {
+                    for p in rasterize(&self.lines[i].0, &self.lines[i].1, self.raster) {
+                        if bbox.contains(&p) {
+                            *counts.entry(p).or_default() += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut retval: Vec<(Point, u32)> = counts.into_iter().filter(|(_, c)| *c >= k).collect();
+        retval.sort_unstable_by_key(|(p, _)| (p.y, p.x));
+        retval
+    }
+}
+
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
     let mut lines: Vec<(Point, Point)> = vec![];
+    let mut raster = Raster::Lattice;
+    let mut ascii = false;
+    let mut pgm: Option<String> = None;
+    let mut ppm: Option<String> = None;
+    let mut crop: Option<BBox> = None;
+    let mut at_queries: Vec<Point> = vec![];
+    let mut cell_queries: Vec<(BBox, u32)> = vec![];
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--lattice" {
+            raster = Raster::Lattice;
+        } else if arg == "--touched" {
+            raster = Raster::Touched;
+        } else if arg == "--ascii" {
+            ascii = true;
+        } else if let Some(path) = arg.strip_prefix("--pgm=") {
+            pgm = Some(path.to_string());
+        } else if let Some(path) = arg.strip_prefix("--ppm=") {
+            ppm = Some(path.to_string());
+        } else if let Some(b) = arg.strip_prefix("--crop=") {
+            crop = Some(b.parse().unwrap_or_else(|e: String| panic!("{}", e)));
+        } else if let Some(pt) = arg.strip_prefix("--at=") {
+            at_queries.push(Point::new(pt));
+        } else if let Some(q) = arg.strip_prefix("--cells=") {
+            let mut iter = q.split(':');
+            let bbox = iter
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|e: String| panic!("{}", e));
+            let k = iter.next().map_or(2, |k| {
+                k.parse::<u32>()
+                    .ok()
+                    .filter(|k| *k > 0)
+                    .expect("bad count, it has to be at least 1")
+            });
+            cell_queries.push((bbox, k));
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
 
     while reader.read_line(&mut inputstr)? != 0 {
-        let mut iter = inputstr.trim().split(" -> ");
-        let from_pt = Point::new(iter.next().unwrap());
-        let to_pt = Point::new(iter.next().unwrap());
-        lines.push((from_pt, to_pt));
+        let input = inputstr.trim();
+        if !input.is_empty() {
+            let mut iter = input.split(" -> ");
+            let from_pt = Point::new(iter.next().unwrap());
+            let to_pt = Point::new(iter.next().unwrap());
+            lines.push((from_pt, to_pt));
+        }
 
         inputstr.clear();
     }
 
-    let mut hm: HashMap<Point, u16> = HashMap::new();
+    // for part one only deal with horizontal or vertical lines, part two adds in all the others
+    let straight: Vec<&(Point, Point)> = lines
+        .iter()
+        .filter(|l| l.0.x == l.1.x || l.0.y == l.1.y)
+        .collect();
+    let all: Vec<&(Point, Point)> = lines.iter().collect();
 
-    // for part one only deal with horizontal or vertical lines
-    for l in &lines {
-        if l.0.x == l.1.x || l.0.y == l.1.y {
-            if l.0.x == l.1.x {
-                let ystart = std::cmp::min(l.0.y, l.1.y);
-                let yend = std::cmp::max(l.0.y, l.1.y);
-                for y in ystart..=yend {
-                    let count = hm.entry(Point { x: l.0.x, y }).or_insert(0);
-                    *count += 1;
-                }
-            } else {
-                let xstart = std::cmp::min(l.0.x, l.1.x);
-                let xend = std::cmp::max(l.0.x, l.1.x);
-                for x in xstart..=xend {
-                    let count = hm.entry(Point { x, y: l.0.y }).or_insert(0);
-                    *count += 1;
+    for (label, ls) in [("aoc5a", &straight), ("aoc5b", &all)] {
+        let ans = match raster {
+            Raster::Lattice => {
+                let segments: Vec<Segment> = ls.iter().map(|l| Segment::new(&l.0, &l.1)).collect();
+                count_overlaps(&segments)
+            }
+            Raster::Touched => {
+                let mut hm: HashMap<Point, u32> = HashMap::new();
+                for l in ls {
+                    for p in rasterize(&l.0, &l.1, raster) {
+                        *hm.entry(p).or_insert(0) += 1;
+                    }
                 }
+                hm.values().filter(|val| **val >= 2).count()
+            }
+        };
+        println!("{}: {}", label, ans);
+    }
+
+    if ascii || pgm.is_some() || ppm.is_some() {
+        let bbox = crop.unwrap_or_else(|| BBox::of_lines(&lines));
+        let counts = render_counts(&lines, raster, &bbox);
+        if ascii {
+            print_ascii(&counts);
+        }
+        if let Some(path) = pgm {
+            write_image(&path, &counts, false)?;
+        }
+        if let Some(path) = ppm {
+            write_image(&path, &counts, true)?;
+        }
+    }
+
+    let map = VentMap::new(&lines, raster);
+    for p in &at_queries {
+        let through = map.lines_through(p);
+        println!("at {},{}: {} lines", p.x, p.y, through.len());
+        for i in through {
+            let l = &lines[i];
+            println!("  line {}: {},{} -> {},{}", i, l.0.x, l.0.y, l.1.x, l.1.y);
+        }
+    }
+    for (bbox, k) in &cell_queries {
+        let cells = map.cells_at_least(bbox, *k);
+        println!(
+            "cells in {},{} - {},{} with at least {} lines: {}",
+            bbox.xmin,
+            bbox.ymin,
+            bbox.xmax,
+            bbox.ymax,
+            k,
+            cells.len()
+        );
+        for (p, c) in cells {
+            println!("  {},{}: {}", p.x, p.y, c);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(lines: &[(i64, i64, i64, i64)]) -> Vec<Segment> {
+        lines
+            .iter()
+            .map(|(x1, y1, x2, y2)| {
+                Segment::new(&Point { x: *x1, y: *y1 }, &Point { x: *x2, y: *y2 })
+            })
+            .collect()
+    }
+
+    // count_overlaps has to agree with rasterizing every line
+    fn check(lines: &[(i64, i64, i64, i64)]) {
+        let mut covered: HashMap<Point, u32> = HashMap::new();
+        for (x1, y1, x2, y2) in lines {
+            let (p1, p2) = (Point { x: *x1, y: *y1 }, Point { x: *x2, y: *y2 });
+            for p in rasterize(&p1, &p2, Raster::Lattice) {
+                *covered.entry(p).or_default() += 1;
             }
         }
+        let brute = covered.values().filter(|c| **c >= 2).count();
+        assert_eq!(count_overlaps(&segments(lines)), brute, "lines {:?}", lines);
     }
 
-    let ans = hm.values().filter(|val| *val >= &2).count();
-    println!("aoc5a: {}", ans);
+    #[test]
+    fn example() {
+        let lines = [
+            (0, 9, 5, 9),
+            (8, 0, 0, 8),
+            (9, 4, 3, 4),
+            (2, 2, 2, 1),
+            (7, 0, 7, 4),
+            (6, 4, 2, 0),
+            (0, 9, 2, 9),
+            (3, 4, 1, 4),
+            (0, 0, 8, 8),
+            (5, 5, 8, 2),
+        ];
+        assert_eq!(count_overlaps(&segments(&lines)), 12);
+        check(&lines);
+    }
 
-    // for part two add in diagonal lines
-    for l in &lines {
-        if l.0.x != l.1.x && l.0.y != l.1.y {
-            let ystart = std::cmp::min(l.0.y, l.1.y);
-            let yend = std::cmp::max(l.0.y, l.1.y);
-            let (xstart, xend) = if ystart == l.0.y {
-                (l.0.x, l.1.x)
-            } else {
-                (l.1.x, l.0.x)
+    #[test]
+    fn collinear_and_crossing() {
+        // a crossing inside a collinear overlap
+        check(&[(0, 0, 6, 0), (2, 0, 8, 0), (4, -3, 4, 3)]);
+        // crossings inside overlaps on both lines
+        check(&[(0, 0, 6, 0), (0, 0, 6, 0), (3, -3, 3, 3), (3, -3, 3, 3)]);
+        // lines of other slopes, crossing on and between lattice points
+        check(&[(0, 0, 6, 3), (2, 1, 10, 5), (0, 3, 6, 0), (0, 1, 1, 0)]);
+        // single points
+        check(&[(1, 1, 1, 1), (1, 1, 1, 1), (0, 0, 2, 2), (5, 5, 5, 5)]);
+    }
+
+    #[test]
+    fn random() {
+        let mut state: u64 = 2021;
+        let mut next = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % n) as i64
+        };
+        for _ in 0..500 {
+            let len = 1 + next(8);
+            let lines: Vec<(i64, i64, i64, i64)> = (0..len)
+                .map(|_| (next(13) - 6, next(13) - 6, next(13) - 6, next(13) - 6))
+                .collect();
+            check(&lines);
+        }
+    }
+
+    // cells_at_least has to agree with rendering the whole box
+    #[test]
+    fn cells_match_render() {
+        let mut state: u64 = 2021;
+        let mut next = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % n) as i64
+        };
+        for _ in 0..200 {
+            let lines: Vec<(Point, Point)> = (0..1 + next(8))
+                .map(|_| {
+                    let mut p = || Point {
+                        x: next(13) - 6,
+                        y: next(13) - 6,
+                    };
+                    (p(), p())
+                })
+                .collect();
+            let (x, y) = (next(9) - 6, next(9) - 6);
+            let bbox = BBox {
+                xmin: x,
+                ymin: y,
+                xmax: x + next(8),
+                ymax: y + next(8),
             };
-            let neg: bool = xstart > xend;
-            for incr in 0..=yend - ystart {
-                let count = hm
-                    .entry(Point {
-                        x: if neg { xstart - incr } else { xstart + incr },
-                        y: ystart + incr,
-                    })
-                    .or_insert(0);
-                *count += 1;
+            let k = 1 + next(3) as u32;
+            for raster in [Raster::Lattice, Raster::Touched] {
+                let mut dense = vec![];
+                for (y, row) in render_counts(&lines, raster, &bbox).iter().enumerate() {
+                    for (x, c) in row.iter().enumerate().filter(|(_, c)| **c >= k) {
+                        let (x, y) = (bbox.xmin + x as i64, bbox.ymin + y as i64);
+                        dense.push((Point { x, y }, *c));
+                    }
+                }
+                let map = VentMap::new(&lines, raster);
+                assert_eq!(map.cells_at_least(&bbox, k), dense, "lines {:?}", lines);
             }
         }
     }
 
-    let ans = hm.values().filter(|val| *val >= &2).count();
-    println!("aoc5b: {}", ans);
+    #[test]
+    fn cells_in_huge_box() {
+        let (min, max) = (i64::MIN, i64::MAX);
+        let lines = [
+            (
+                Point { x: min, y: min },
+                Point {
+                    x: min + 4,
+                    y: min + 4,
+                },
+            ),
+            (Point { x: min, y: min + 4 }, Point { x: min + 4, y: min }),
+            (Point { x: max, y: max }, Point { x: max, y: max }),
+            (Point { x: max, y: max }, Point { x: max, y: max }),
+        ];
+        let map = VentMap::new(&lines, Raster::Lattice);
+        let bbox = BBox {
+            xmin: min,
+            ymin: min,
+            xmax: max,
+            ymax: max,
+        };
+        let crossing = Point {
+            x: min + 2,
+            y: min + 2,
+        };
+        let corner = Point { x: max, y: max };
+        assert_eq!(map.cells_at_least(&bbox, 2), [(crossing, 2), (corner, 2)]);
+        assert_eq!(map.cells_at_least(&bbox, 1).len(), 10);
+    }
 
-    Ok(())
+    #[test]
+    fn bbox_sizes() {
+        let bbox: BBox = "-2,0,7,3".parse().unwrap();
+        assert_eq!((bbox.width(), bbox.height()), (Some(10), Some(4)));
+        // the whole range is one more than fits in a 64 bit usize
+        let bbox: BBox = format!("{},0,{},0", i64::MIN, i64::MAX).parse().unwrap();
+        assert_eq!((bbox.width(), bbox.height()), (None, Some(1)));
+    }
+
+    #[test]
+    fn extreme_coordinates() {
+        let (min, max) = (i64::MIN, i64::MAX);
+
+        // a steep line with coprime steps of about 2^63, twice
+        let half = 1 << 62;
+        let lines = [(-half, -half + 1, half - 1, half - 1); 2];
+        assert_eq!(count_overlaps(&segments(&lines)), 2);
+
+        // lines across the whole range crossing at the origin, and a short overlap at one end
+        let lines = [
+            (min, 0, max, 0),
+            (0, min, 0, max),
+            (min, min, max, max),
+            (min, 0, min + 10, 0),
+        ];
+        assert_eq!(count_overlaps(&segments(&lines)), 12);
+
+        // a single step of nearly 2^64 each way, crossed at its start and covered at its end
+        let lines = [
+            (min, min, max, max - 1),
+            (min, min, min + 5, min),
+            (max, max - 1, max, max - 1),
+        ];
+        assert_eq!(count_overlaps(&segments(&lines)), 2);
+    }
 }
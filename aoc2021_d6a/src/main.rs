@@ -1,47 +1,468 @@
 // AoC 2021 day 6
+//
+// the population after any number of days can be projected with:
+//   aoc2021_d6a [--days=N ...] [--mod=M] < input
+// where N can go up to 10^18.  One day of the simulation is a linear map on the 9 timer counts,
+// so N days is that 9x9 transition matrix raised to the Nth power, computed by repeated
+// squaring.  The counts are exact by default, but they grow by about 9% a day so past a few
+// hundred thousand days only the count modulo M (usually a large prime) is practical
+//
+// the life cycle is not fixed to that of the lanternfish, --species=FILE reads one species per
+// line as:
+//   name cycle=C [delay=D] [litter=L] [offspring=NAME] [matures=NAME]
+// a fish of the species spawns every C days and its newborns wait D more days before their
+// first cycle starts (the lanternfish is "lanternfish cycle=7 delay=2").  Each spawn gives L
+// newborns (default 1) of the offspring species (default itself).  A species that matures
+// doesn't spawn, when its timer runs out it becomes a fish of the other species instead, at
+// the start of a fresh cycle.  The input then gives each species its timers as
+//   name: t,t,t
+// with a line of bare timers going to the first species.  Several species run in the same
+// simulation and --csv=FILE writes the population of each species for every day up to
+// --csv-days=N (default 256)
 
-// perform one step of the lantern fish simulation
-fn one_step(v: &[usize]) -> Vec<usize> {
-    let mut next_v: Vec<_> = vec![0; v.len()];
-    for i in 0..v.len() {
-        if i == 0 {
-            next_v[6] += v[i];
-            next_v[8] += v[i];
-        } else {
-            next_v[i - 1] += v[i];
+use std::io::Write;
+
+// arbitrary precision unsigned integer, little endian base 2^32 limbs without trailing zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn from_u64(n: u64) -> Self {
+        let mut bu = Self {
+            limbs: vec![n as u32, (n >> 32) as u32],
+        };
+        bu.trim();
+        bu
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    // divide in place by a small divisor, returning the remainder
+    fn div_rem_small(&mut self, d: u32) -> u32 {
+        let mut rem = 0_u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let cur = (rem << 32) | *limb as u64;
+            *limb = (cur / d as u64) as u32;
+            rem = cur % d as u64;
+        }
+        self.trim();
+        rem as u32
+    }
+}
+
+impl std::fmt::Display for BigUint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // peel off 9 decimal digits at a time
+        let mut n = self.clone();
+        let mut chunks: Vec<u32> = vec![];
+        while !n.is_zero() {
+            chunks.push(n.div_rem_small(1_000_000_000));
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for c in chunks.iter().rev() {
+            write!(f, "{:09}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// the arithmetic the fish are counted in
+trait Count: Clone + PartialEq {
+    fn add(&self, other: &Self) -> Self;
+    fn mul(&self, other: &Self) -> Self;
+}
+
+impl Count for BigUint {
+    fn add(&self, other: &Self) -> Self {
+        let mut limbs: Vec<u32> = Vec::with_capacity(self.limbs.len().max(other.limbs.len()) + 1);
+        let mut carry = 0_u64;
+        for i in 0..self.limbs.len().max(other.limbs.len()) {
+            let a = *self.limbs.get(i).unwrap_or(&0) as u64;
+            let b = *other.limbs.get(i).unwrap_or(&0) as u64;
+            let sum = a + b + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+
+        let mut bu = Self { limbs };
+        bu.trim();
+        bu
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut limbs: Vec<u32> = vec![0; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0_u64;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let cur = limbs[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = cur as u32;
+                carry = cur >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+
+        let mut bu = Self { limbs };
+        bu.trim();
+        bu
+    }
+}
+
+// a count modulo m
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModCount {
+    v: u64,
+    m: u64,
+}
+
+impl ModCount {
+    fn new(v: u64, m: u64) -> Self {
+        Self { v: v % m, m }
+    }
+}
+
+impl Count for ModCount {
+    fn add(&self, other: &Self) -> Self {
+        Self::new(
+            ((self.v as u128 + other.v as u128) % self.m as u128) as u64,
+            self.m,
+        )
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        Self::new(
+            ((self.v as u128 * other.v as u128) % self.m as u128) as u64,
+            self.m,
+        )
+    }
+}
+
+// life cycle of one species
+#[derive(Debug, Clone)]
+struct Species {
+    name: String,
+    cycle: usize,
+    delay: usize,
+    litter: usize,
+    offspring: usize,       // index of the species newborns belong to
+    matures: Option<usize>, // index of the species this one turns into instead of spawning
+}
+
+impl Species {
+    fn lanternfish() -> Self {
+        Self {
+            name: "lanternfish".to_string(),
+            cycle: 7,
+            delay: 2,
+            litter: 1,
+            offspring: 0,
+            matures: None,
+        }
+    }
+
+    // number of timer values a fish of the species can have
+    fn slots(&self) -> usize {
+        self.cycle + self.delay
+    }
+}
+
+// parse a species file, see the top of the file for its format
+fn parse_species(text: &str) -> Result<Vec<Species>, String> {
+    let defs: Vec<Vec<&str>> = text
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .filter(|v| !v.is_empty() && !v[0].starts_with('#'))
+        .collect();
+    let names: Vec<&str> = defs.iter().map(|v| v[0]).collect();
+    let index_of = |n: &str| {
+        names
+            .iter()
+            .position(|name| *name == n)
+            .ok_or(format!("unknown species {}", n))
+    };
+
+    let mut retval: Vec<Species> = vec![];
+    for (idx, def) in defs.iter().enumerate() {
+        let mut sp = Species {
+            name: def[0].to_string(),
+            cycle: 0,
+            delay: 0,
+            litter: 1,
+            offspring: idx,
+            matures: None,
+        };
+        for field in &def[1..] {
+            let (key, val) = field
+                .split_once('=')
+                .ok_or(format!("bad field {} for species {}", field, sp.name))?;
+            let num = || {
+                val.parse::<usize>()
+                    .map_err(|_| format!("bad value {} for {}", val, key))
+            };
+            match key {
+                "cycle" => sp.cycle = num()?,
+                "delay" => sp.delay = num()?,
+                "litter" => sp.litter = num()?,
+                "offspring" => sp.offspring = index_of(val)?,
+                "matures" => sp.matures = Some(index_of(val)?),
+                _ => return Err(format!("unknown field {} for species {}", key, sp.name)),
+            }
+        }
+        if sp.cycle == 0 {
+            return Err(format!("species {} needs a cycle of at least 1", sp.name));
+        }
+        retval.push(sp);
+    }
+
+    if retval.is_empty() {
+        return Err("no species defined".to_string());
+    }
+    Ok(retval)
+}
+
+// where each species' timer counts start in the population vector
+fn offsets(species: &[Species]) -> Vec<usize> {
+    species
+        .iter()
+        .scan(0, |off, sp| {
+            let o = *off;
+            *off += sp.slots();
+            Some(o)
+        })
+        .collect()
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+// the linear map taking the population of one day to the next, m[to][from]
+fn transition<T: Count>(species: &[Species], zero: &T, one: &T) -> Matrix<T> {
+    let offs = offsets(species);
+    let n: usize = species.iter().map(|sp| sp.slots()).sum();
+    let mut m = vec![vec![zero.clone(); n]; n];
+
+    for (sp, off) in species.iter().zip(&offs) {
+        // every timer moves down by one
+        for t in 1..sp.slots() {
+            m[off + t - 1][off + t] = m[off + t - 1][off + t].add(one);
+        }
+
+        // timer 0 either matures or restarts its cycle and spawns
+        match sp.matures {
+            Some(adult) => {
+                let to = offs[adult] + species[adult].cycle - 1;
+                m[to][*off] = m[to][*off].add(one);
+            }
+            None => {
+                let to = off + sp.cycle - 1;
+                m[to][*off] = m[to][*off].add(one);
+
+                let young = &species[sp.offspring];
+                let to = offs[sp.offspring] + young.slots() - 1;
+                for _ in 0..sp.litter {
+                    m[to][*off] = m[to][*off].add(one);
+                }
+            }
         }
     }
-    next_v
+
+    m
+}
+
+fn mat_vec<T: Count>(m: &Matrix<T>, v: &[T], zero: &T) -> Vec<T> {
+    m.iter()
+        .map(|row| {
+            row.iter()
+                .zip(v)
+                .filter(|(c, _)| *c != zero)
+                .fold(zero.clone(), |s, (c, x)| s.add(&c.mul(x)))
+        })
+        .collect()
+}
+
+// population of each species
+fn species_totals<T: Count>(species: &[Species], pop: &[T], zero: &T) -> Vec<T> {
+    species
+        .iter()
+        .zip(offsets(species))
+        .map(|(sp, off)| {
+            pop[off..off + sp.slots()]
+                .iter()
+                .fold(zero.clone(), |s, x| s.add(x))
+        })
+        .collect()
+}
+
+fn mat_mul<T: Count>(a: &Matrix<T>, b: &Matrix<T>, zero: &T) -> Matrix<T> {
+    let n = a.len();
+    let mut c = vec![vec![zero.clone(); n]; n];
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k] == *zero {
+                continue; // the transition matrix is sparse, skip the pointless products
+            }
+            for j in 0..n {
+                c[i][j] = c[i][j].add(&a[i][k].mul(&b[k][j]));
+            }
+        }
+    }
+    c
+}
+
+// the matrix m raised to the power p by repeated squaring
+fn mat_pow<T: Count>(m: &Matrix<T>, mut p: u64, zero: &T, one: &T) -> Matrix<T> {
+    let n = m.len();
+    let mut result: Matrix<T> = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { one.clone() } else { zero.clone() })
+                .collect()
+        })
+        .collect();
+    let mut base = m.clone();
+    while p > 0 {
+        if p & 1 == 1 {
+            result = mat_mul(&result, &base, zero);
+        }
+        p >>= 1;
+        if p > 0 {
+            base = mat_mul(&base, &base, zero);
+        }
+    }
+    result
+}
+
+// the population after days, counted in whatever arithmetic zero and one come from
+fn project<T: Count>(species: &[Species], pop: &[T], days: u64, zero: &T, one: &T) -> Vec<T> {
+    let mp = mat_pow(&transition(species, zero, one), days, zero, one);
+    mat_vec(&mp, pop, zero)
+}
+
+// write the population of each species for every day of the series
+fn write_csv(path: &str, species: &[Species], series: &[Vec<BigUint>]) -> std::io::Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let names: Vec<&str> = species.iter().map(|sp| sp.name.as_str()).collect();
+    writeln!(out, "day,{},total", names.join(","))?;
+
+    let zero = BigUint::from_u64(0);
+    for (day, pop) in series.iter().enumerate() {
+        let totals = species_totals(species, pop, &zero);
+        let total = totals.iter().fold(zero.clone(), |s, x| s.add(x));
+        let cols: Vec<String> = totals.iter().map(|t| t.to_string()).collect();
+        writeln!(out, "{},{},{}", day, cols.join(","), total)?;
+    }
+
+    out.flush()
 }
 
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
-    let mut timers: Vec<usize> = vec![0; 9];
+    let mut days: Vec<u64> = vec![];
+    let mut modulus: Option<u64> = None;
+    let mut species = vec![Species::lanternfish()];
+    let mut csv: Option<String> = None;
+    let mut csv_days = 256;
 
+    for arg in std::env::args().skip(1) {
+        if let Some(d) = arg.strip_prefix("--days=") {
+            days.push(d.parse::<u64>().expect("bad number of days"));
+        } else if let Some(m) = arg.strip_prefix("--mod=") {
+            let m = m.parse::<u64>().expect("bad modulus");
+            if m == 0 {
+                panic!("the modulus must be at least 1");
+            }
+            modulus = Some(m);
+        } else if let Some(path) = arg.strip_prefix("--species=") {
+            species = parse_species(&std::fs::read_to_string(path)?)
+                .unwrap_or_else(|e| panic!("{}: {}", path, e));
+        } else if let Some(path) = arg.strip_prefix("--csv=") {
+            csv = Some(path.to_string());
+        } else if let Some(d) = arg.strip_prefix("--csv-days=") {
+            csv_days = d.parse::<usize>().expect("bad number of days");
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
+
+    let offs = offsets(&species);
+    let mut timers: Vec<u64> = vec![0; species.iter().map(|sp| sp.slots()).sum()];
     while reader.read_line(&mut inputstr)? != 0 {
-        let iter = inputstr.trim().split(',');
-        for s in iter {
+        let input = inputstr.trim();
+        let (idx, list) = match input.split_once(':') {
+            Some((name, list)) => (
+                species
+                    .iter()
+                    .position(|sp| sp.name == name.trim())
+                    .unwrap_or_else(|| panic!("unknown species {}", name)),
+                list,
+            ),
+            None => (0, input),
+        };
+
+        for s in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
             let timer = s.parse::<usize>().unwrap();
-            timers[timer] += 1;
+            if timer >= species[idx].slots() {
+                panic!(
+                    "timer {} is too big for species {}",
+                    timer, species[idx].name
+                );
+            }
+            timers[offs[idx] + timer] += 1;
         }
 
         inputstr.clear();
     }
 
-    for _ in 0..80 {
-        timers = one_step(&timers);
+    // step through the days one at a time keeping the whole series
+    let zero = BigUint::from_u64(0);
+    let one = BigUint::from_u64(1);
+    let m = transition(&species, &zero, &one);
+    let mut series: Vec<Vec<BigUint>> =
+        vec![timers.iter().map(|n| BigUint::from_u64(*n)).collect()];
+    for _ in 0..csv_days.max(256) {
+        let next = mat_vec(&m, series.last().unwrap(), &zero);
+        series.push(next);
     }
 
-    let ans: usize = timers.iter().sum();
-    println!("aoc6a: {}", ans);
+    let total = |day: usize| series[day].iter().fold(zero.clone(), |s, x| s.add(x));
+    println!("aoc6a: {}", total(80));
+    println!("aoc6b: {}", total(256));
 
-    for _ in 80..256 {
-        timers = one_step(&timers);
+    if let Some(path) = csv {
+        write_csv(&path, &species, &series[..=csv_days])?;
     }
 
-    let ans: usize = timers.iter().sum();
-    println!("aoc6b: {}", ans);
+    for d in &days {
+        match modulus {
+            Some(m) => {
+                let (zero, one) = (ModCount::new(0, m), ModCount::new(1, m));
+                let t: Vec<ModCount> = timers.iter().map(|n| ModCount::new(*n, m)).collect();
+                let pop = project(&species, &t, *d, &zero, &one);
+                let c = pop.iter().fold(zero, |s, x| s.add(x));
+                println!("day {}: {} (mod {})", d, c.v, m);
+            }
+            None => {
+                let t: Vec<BigUint> = timers.iter().map(|n| BigUint::from_u64(*n)).collect();
+                let pop = project(&species, &t, *d, &zero, &one);
+                let c = pop.iter().fold(zero.clone(), |s, x| s.add(x));
+                println!("day {}: {}", d, c);
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1,63 +1,433 @@
 // AoC 2021 day 7
+//
+// rather than trying every position the best one is found directly:
+//   part one's cost is a sum of distances, minimized anywhere between the two middle crabs
+//   (the median)
+//   part two's cost is a sum of triangular numbers of the distances, it is strictly convex and
+//   minimized within half a step of the mean, so only the positions next to the mean are tried
+//   other costs without a closed form are minimized by ternary search if they are convex and by
+//   trying every position if they're not
+//
+//   aoc2021_d7a [--positions] [--verify] [--cost=linear|triangular|quadratic|table:FILE ...]
+// --positions shows where the crabs align and --verify checks the answers against trying every
+// position from the leftmost to the rightmost crab.  Each --cost adds an alignment using that
+// cost, a table file lists the (non-decreasing) cost of moving 0, 1, 2, ... steps and crabs can't
+// move further than the table goes
+//
+// a crab can be given as pos*weight to make its fuel cost weight times as much, and crabs can
+// be on a grid as x:y (all or none of them).  A crab on the grid moves along each axis in turn,
+// paying for each leg separately, so each axis is aligned on its own
 
-// calculate fuel cost to realign all crabs to pos in part 1
-fn realignment_cost1(v: &[u32], pos: u32) -> u32 {
-    v.iter()
-        .map(|p| if *p < pos { pos - p } else { p - pos })
-        .sum()
+use std::convert::TryFrom;
+
+// a crab on one axis
+#[derive(Debug, Clone, Copy)]
+struct Crab {
+    pos: u64,
+    weight: u64,
+}
+
+// the best positions to align to (an inclusive range) and what it costs
+#[derive(Debug, PartialEq, Eq)]
+struct Alignment {
+    positions: (u64, u64),
+    cost: u64,
+}
+
+// the cost of moving a crab some distance
+trait FuelCost {
+    fn cost(&self, distance: u64) -> u128;
+
+    // how far a crab can move, if there is a limit
+    fn max_distance(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+
+    // a small range of positions known to hold every optimal one, if the cost has a closed form
+    fn optimal_range(&self, _crabs: &[Crab]) -> Option<(u64, u64)> {
+        None
+    }
+
+    // whether every position of optimal_range is optimal, rather than just a candidate
+    fn range_is_exact(&self) -> bool {
+        false
+    }
+}
+
+// the positions around the weighted mean of the crabs
+fn around_mean(crabs: &[Crab]) -> Option<(u64, u64)> {
+    let total: u128 = crabs.iter().map(|c| c.weight as u128).sum();
+    if total == 0 {
+        return None;
+    }
+    let sum: u128 = crabs.iter().map(|c| c.pos as u128 * c.weight as u128).sum();
+    let mean = (sum / total) as u64;
+    Some((mean.saturating_sub(1), mean + 2))
+}
+
+// cost of moving a crab in part 1
+struct Linear;
+
+impl FuelCost for Linear {
+    fn cost(&self, distance: u64) -> u128 {
+        distance as u128
+    }
+
+    // every position between the weighted medians
+    fn optimal_range(&self, crabs: &[Crab]) -> Option<(u64, u64)> {
+        let mut sorted = crabs.to_vec();
+        sorted.sort_unstable_by_key(|c| c.pos);
+        let total: u128 = crabs.iter().map(|c| c.weight as u128).sum();
+
+        let mut cum = 0;
+        let mut lo = None;
+        for c in &sorted {
+            cum += c.weight as u128;
+            if lo.is_none() && cum * 2 >= total {
+                lo = Some(c.pos);
+            }
+            if cum * 2 > total {
+                return Some((lo.unwrap(), c.pos));
+            }
+        }
+        None
+    }
+
+    // the cost is the same anywhere between the weighted medians
+    fn range_is_exact(&self) -> bool {
+        true
+    }
+}
+
+// cost of moving a crab in part 2
+struct Triangular;
+
+impl FuelCost for Triangular {
+    fn cost(&self, distance: u64) -> u128 {
+        distance as u128 * (distance as u128 + 1) / 2
+    }
+
+    // the optimum is within half a step of the weighted mean
+    fn optimal_range(&self, crabs: &[Crab]) -> Option<(u64, u64)> {
+        around_mean(crabs)
+    }
+}
+
+// each step costs more than the last by two
+struct Quadratic;
+
+impl FuelCost for Quadratic {
+    fn cost(&self, distance: u64) -> u128 {
+        distance as u128 * distance as u128
+    }
+
+    // the optimum of a sum of squares is at the weighted mean
+    fn optimal_range(&self, crabs: &[Crab]) -> Option<(u64, u64)> {
+        around_mean(crabs)
+    }
+}
+
+// cost of moving each distance looked up in a table
+struct Table {
+    costs: Vec<u128>,
+}
+
+impl Table {
+    fn new(text: &str) -> Result<Self, String> {
+        let costs: Vec<u128> = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<u128>().map_err(|_| format!("bad cost {}", s)))
+            .collect::<Result<_, _>>()?;
+        if costs.is_empty() {
+            return Err("empty cost table".to_string());
+        }
+        if costs.windows(2).any(|w| w[1] < w[0]) {
+            return Err("costs must not go down with distance".to_string());
+        }
+        Ok(Self { costs })
+    }
+}
+
+impl FuelCost for Table {
+    fn cost(&self, distance: u64) -> u128 {
+        self.costs[distance as usize]
+    }
+
+    fn max_distance(&self) -> Option<u64> {
+        Some(self.costs.len() as u64 - 1)
+    }
+
+    // the step costs never go down
+    fn is_convex(&self) -> bool {
+        self.costs.windows(3).all(|w| w[2] - w[1] >= w[1] - w[0])
+    }
 }
 
-// calculate fuel cost to realign all crabs to pos in part 2
-fn realignment_cost2(v: &[u32], pos: u32) -> u32 {
-    v.iter()
-        .map(|p| if *p < pos { pos - p } else { p - pos })
-        .map(|n| n * (n + 1) / 2)
-        .sum()
+fn parse_cost(s: &str) -> Box<dyn FuelCost> {
+    match s {
+        "linear" => Box::new(Linear),
+        "triangular" => Box::new(Triangular),
+        "quadratic" => Box::new(Quadratic),
+        _ => match s.strip_prefix("table:") {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("can't read {}: {}", path, e));
+                Box::new(Table::new(&text).unwrap_or_else(|e| panic!("{}: {}", path, e)))
+            }
+            None => panic!("unknown cost {}", s),
+        },
+    }
+}
+
+// the sum of the costs of moving every crab to pos
+fn total_cost(crabs: &[Crab], pos: u64, cost: &dyn FuelCost) -> u64 {
+    let sum: u128 = crabs
+        .iter()
+        .map(|c| cost.cost(c.pos.abs_diff(pos)) * c.weight as u128)
+        .sum();
+    u64::try_from(sum).expect("fuel cost overflows u64")
+}
+
+// try every position of the range, the optimal ones of a convex cost are contiguous
+fn best_of(crabs: &[Crab], (lo, hi): (u64, u64), cost: &dyn FuelCost) -> Alignment {
+    let mut best: Option<Alignment> = None;
+    for p in lo..=hi {
+        let c = total_cost(crabs, p, cost);
+        match &mut best {
+            Some(b) if c == b.cost => b.positions.1 = p,
+            Some(b) if c > b.cost => {}
+            _ => {
+                best = Some(Alignment {
+                    positions: (p, p),
+                    cost: c,
+                })
+            }
+        }
+    }
+    best.expect("no positions to try")
+}
+
+// ternary search for the minimum of a convex cost, then widen it to every position sharing it
+fn ternary_search(crabs: &[Crab], (lo, hi): (u64, u64), cost: &dyn FuelCost) -> Alignment {
+    let f = |p: u64| total_cost(crabs, p, cost);
+    let (mut l, mut r) = (lo, hi);
+    while r - l > 2 {
+        let m1 = l + (r - l) / 3;
+        let m2 = r - (r - l) / 3;
+        if f(m1) <= f(m2) {
+            r = m2;
+        } else {
+            l = m1;
+        }
+    }
+    let best = best_of(crabs, (l, r), cost);
+
+    // the cost falls to the minimum and rises after it, binary search for the plateau's ends
+    let (mut l, mut r) = (lo, best.positions.0);
+    while l < r {
+        let m = l + (r - l) / 2;
+        if f(m) == best.cost {
+            r = m;
+        } else {
+            l = m + 1;
+        }
+    }
+    let first = l;
+
+    let (mut l, mut r) = (best.positions.1, hi);
+    while l < r {
+        let m = l + (r - l).div_ceil(2);
+        if f(m) == best.cost {
+            l = m;
+        } else {
+            r = m - 1;
+        }
+    }
+
+    Alignment {
+        positions: (first, l),
+        cost: best.cost,
+    }
+}
+
+// the range of positions every crab can reach
+fn reachable(crabs: &[Crab], cost: &dyn FuelCost) -> (u64, u64) {
+    let lo = crabs.iter().map(|c| c.pos).min().expect("no crabs");
+    let hi = crabs.iter().map(|c| c.pos).max().unwrap();
+    match cost.max_distance() {
+        Some(d) if hi - lo > 2 * d => panic!("no position can be reached by every crab"),
+        Some(d) => (lo.max(hi.saturating_sub(d)), hi.min(lo + d)),
+        None => (lo, hi),
+    }
+}
+
+// find the cheapest positions to align the crabs to
+fn align(crabs: &[Crab], cost: &dyn FuelCost) -> Alignment {
+    let (lo, hi) = reachable(crabs, cost);
+    match cost.optimal_range(crabs) {
+        Some((l, h)) if cost.range_is_exact() => {
+            let positions = (l.max(lo), h.min(hi));
+            Alignment {
+                positions,
+                cost: total_cost(crabs, positions.0, cost),
+            }
+        }
+        Some((l, h)) => best_of(crabs, (l.max(lo), h.min(hi)), cost),
+        None if cost.is_convex() => ternary_search(crabs, (lo, hi), cost),
+        None => best_of(crabs, (lo, hi), cost),
+    }
 }
 
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
-    let mut hor_pos: Vec<u32> = vec![];
-    let mut largest = 0;
-    let mut smallest = 0;
+    let mut axes: Vec<Vec<Crab>> = vec![];
+    let mut show_positions = false;
+    let mut verify = false;
+    let mut costs: Vec<(String, Box<dyn FuelCost>)> = vec![
+        ("aoc7a".to_string(), Box::new(Linear)),
+        ("aoc7b".to_string(), Box::new(Triangular)),
+    ];
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--positions" {
+            show_positions = true;
+        } else if arg == "--verify" {
+            verify = true;
+        } else if let Some(c) = arg.strip_prefix("--cost=") {
+            costs.push((format!("cost {}", c), parse_cost(c)));
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
 
     while reader.read_line(&mut inputstr)? != 0 {
-        let iter = inputstr.trim().split(',');
+        let iter = inputstr.trim().split(',').filter(|s| !s.is_empty());
 
         for s in iter {
-            let pos = s.parse::<u32>().expect("bad horizontal position");
-            hor_pos.push(pos);
-            if pos < smallest {
-                smallest = pos;
+            let (pos, weight) = match s.split_once('*') {
+                Some((p, w)) => (p, w.parse::<u64>().expect("bad crab weight")),
+                None => (s, 1),
+            };
+            let coords: Vec<u64> = pos
+                .split(':')
+                .map(|p| p.trim().parse::<u64>().expect("bad horizontal position"))
+                .collect();
+            if axes.is_empty() {
+                axes = vec![vec![]; coords.len()];
+            } else if coords.len() != axes.len() {
+                panic!("crab {} doesn't have {} coordinates", s, axes.len());
             }
-            if pos > largest {
-                largest = pos;
+            for (axis, p) in axes.iter_mut().zip(coords) {
+                axis.push(Crab { pos: p, weight });
             }
         }
 
         inputstr.clear();
     }
 
-    // part one
-    let mut least_fuel = u32::MAX;
-    for p in smallest..largest {
-        let fuel = realignment_cost1(&hor_pos, p);
-        if fuel < least_fuel {
-            least_fuel = fuel;
+    for (label, cost) in &costs {
+        let alignments: Vec<Alignment> = axes.iter().map(|a| align(a, cost.as_ref())).collect();
+        println!(
+            "{}: {}",
+            label,
+            alignments.iter().map(|a| a.cost).sum::<u64>()
+        );
+
+        for (axis, a) in axes.iter().zip(&alignments) {
+            if show_positions {
+                println!("  positions {}..={}", a.positions.0, a.positions.1);
+            }
+            if verify {
+                let brute = best_of(axis, reachable(axis, cost.as_ref()), cost.as_ref());
+                if brute != *a {
+                    panic!("{} doesn't match brute force {:?}", label, brute);
+                }
+                println!("  matches brute force");
+            }
         }
     }
-    println!("aoc7a: {}", least_fuel);
 
-    // part two
-    least_fuel = u32::MAX;
-    for p in smallest..largest {
-        let fuel = realignment_cost2(&hor_pos, p);
-        if fuel < least_fuel {
-            least_fuel = fuel;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crabs(positions: &[u64]) -> Vec<Crab> {
+        positions
+            .iter()
+            .map(|p| Crab { pos: *p, weight: 1 })
+            .collect()
+    }
+
+    // align has to agree with trying every reachable position
+    fn check(crabs: &[Crab]) {
+        let costs: [&dyn FuelCost; 3] = [&Linear, &Triangular, &Quadratic];
+        for cost in costs {
+            let brute = best_of(crabs, reachable(crabs, cost), cost);
+            assert_eq!(align(crabs, cost), brute, "crabs {:?}", crabs);
         }
     }
-    println!("aoc7b: {}", least_fuel);
 
-    Ok(())
+    #[test]
+    fn example() {
+        let c = crabs(&[16, 1, 2, 0, 4, 2, 7, 1, 2, 14]);
+        assert_eq!(align(&c, &Linear).cost, 37);
+        assert_eq!(align(&c, &Triangular).cost, 168);
+        check(&c);
+    }
+
+    #[test]
+    fn odd_count() {
+        check(&crabs(&[3, 9, 1, 20, 4]));
+        check(&crabs(&[0, 100, 7]));
+        check(&crabs(&[42]));
+    }
+
+    #[test]
+    fn even_count() {
+        check(&crabs(&[3, 9, 1, 20]));
+        check(&crabs(&[0, 100]));
+        check(&crabs(&[5, 6]));
+    }
+
+    #[test]
+    fn duplicate_positions() {
+        check(&crabs(&[4, 4, 4, 4]));
+        check(&crabs(&[1, 1, 9, 9]));
+        check(&crabs(&[0, 0, 0, 50, 50]));
+    }
+
+    #[test]
+    fn weighted() {
+        check(&[
+            Crab { pos: 1, weight: 3 },
+            Crab { pos: 5, weight: 1 },
+            Crab { pos: 9, weight: 2 },
+            Crab { pos: 20, weight: 0 },
+        ]);
+    }
+
+    #[test]
+    fn random() {
+        let mut state: u64 = 2021;
+        let mut next = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        for _ in 0..200 {
+            let len = 1 + next(12);
+            let positions: Vec<u64> = (0..len).map(|_| next(60)).collect();
+            check(&crabs(&positions));
+        }
+    }
 }
@@ -1,116 +1,610 @@
 // AoC 2021 day 8
+//
+// the wiring of each display is found by constraint propagation rather than hand derived rules.
+// Every wire starts out possibly driving any segment and every pattern (from both sides of the
+// note) possibly showing any symbol of the display table with as many segments lit.  Then,
+// until nothing changes:
+//   a pattern can only show a symbol if each of its wires can drive one of the symbol's lit
+//   segments and each of its dark wires one of the symbol's dark segments
+//   a wire can only drive segments allowed by every pattern's remaining symbols
+//   a segment known to be driven by one wire can't be driven by any other
+// if that doesn't settle every wire one is tried on each segment it could still drive and the
+// propagation repeats.  Notes with no wiring are reported as inconsistent and notes whose
+// wirings disagree on the outputs as ambiguous
+//
+//   aoc2021_d8a [--display=FILE] [--generate=N | --round-trip=N] [--outputs=K] [--seed=S]
+// --display decodes the notes against another display, the file has a line per symbol giving
+// the segments lit for it (segment names are single characters and the wires are named the
+// same), e.g. "A: abcdef" for hex digits on seven segments, blank lines and lines starting with
+// # are skipped.  If the symbols are the digits of a base (0-9 then A-Z) part two sums the
+// outputs read in that base, otherwise each reading is listed instead
+//
+// --generate writes N notes for random numbers of K (default 4) outputs, each scrambled by a
+// random wiring, instead of reading any.  --round-trip decodes the notes it generates and
+// panics if one doesn't read back as the number it was made from
 
-use std::collections::HashMap;
-
-// function that returns a String -> u32 HashMap decoding the passed inputs
-fn decode_inputs(v: &[Vec<char>]) -> HashMap<Vec<char>, u32> {
-    let mut retval: HashMap<Vec<char>, u32> = HashMap::new();
-    let mut vc: Vec<Vec<char>> = vec![vec![]; 10];
-
-    // first get those unique by length
-    for s in v {
-        let mut ss = s.clone();
-        ss.sort_unstable();
-        match s.len() {
-            2 => {
-                retval.insert(ss, 1);
-                vc[1] = s.clone();
+// the symbols a display can show and the segments lit for each, as bitmasks over the segments
+#[derive(Debug)]
+struct Display {
+    segments: Vec<char>, // segment names, also used for the wires
+    symbols: Vec<(String, u32)>,
+}
+
+impl Display {
+    // digits 0-9 on seven segments named a-g
+    fn seven_segment() -> Self {
+        let digits = [
+            "abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg",
+        ];
+        let segments: Vec<char> = ('a'..='g').collect();
+        let symbols = digits
+            .iter()
+            .enumerate()
+            .map(|(d, segs)| (d.to_string(), mask_of(&segments, segs).unwrap()))
+            .collect();
+        Self { segments, symbols }
+    }
+
+    fn all_segments(&self) -> u32 {
+        u32::MAX >> (32 - self.segments.len())
+    }
+
+    // the base the symbols are the digits of, if they are
+    fn radix(&self) -> Option<u32> {
+        let n = self.symbols.len() as u32;
+        if !(2..=36).contains(&n) {
+            return None;
+        }
+        let mut digits: Vec<u32> = self
+            .symbols
+            .iter()
+            .map(|(s, _)| {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c.to_digit(n),
+                    _ => None,
+                }
+            })
+            .collect::<Option<_>>()?;
+        digits.sort_unstable();
+        digits.dedup();
+        if digits.len() == n as usize {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    // the symbols writing value in width digits, if the symbols are the digits of a base
+    fn spell(&self, mut value: u64, width: usize) -> Option<Vec<usize>> {
+        let r = self.radix()?;
+        let mut digits = vec![];
+        for _ in 0..width {
+            let d = (value % r as u64) as u32;
+            value /= r as u64;
+            digits.push(
+                self.symbols
+                    .iter()
+                    .position(|(s, _)| s.chars().next().and_then(|c| c.to_digit(r)) == Some(d))?,
+            );
+        }
+        if value != 0 {
+            return None;
+        }
+        digits.reverse();
+        Some(digits)
+    }
+}
+
+impl std::str::FromStr for Display {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = vec![];
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            3 => {
-                retval.insert(ss, 7);
-                vc[7] = s.clone();
+            let (symbol, segs) = line.split_once(':').ok_or(format!("no : in {}", line))?;
+            let (symbol, segs) = (symbol.trim(), segs.trim());
+            if symbol.is_empty() || segs.is_empty() {
+                return Err(format!("missing symbol or segments in {}", line));
             }
-            4 => {
-                retval.insert(ss, 4);
-                vc[4] = s.clone();
+            if let Some(c) = segs.chars().find(|c| c.is_whitespace() || *c == '|') {
+                return Err(format!("segment can't be named {:?} in {}", c, line));
             }
-            7 => {
-                retval.insert(ss, 8);
-                vc[8] = s.clone();
+            lines.push((symbol, segs));
+        }
+
+        let mut segments: Vec<char> = lines.iter().flat_map(|(_, segs)| segs.chars()).collect();
+        segments.sort_unstable();
+        segments.dedup();
+        if segments.len() > 32 {
+            return Err(format!(
+                "{} segments, at most 32 are supported",
+                segments.len()
+            ));
+        }
+
+        let mut symbols: Vec<(String, u32)> = vec![];
+        for (symbol, segs) in lines {
+            let mask = mask_of(&segments, segs)?;
+            if let Some((other, _)) = symbols.iter().find(|(o, m)| *o == symbol || *m == mask) {
+                return Err(format!(
+                    "symbols {} and {} can't be told apart",
+                    other, symbol
+                ));
             }
-            _ => {}
-        }
-    }
-
-    // figure out those of length 5 and 6 based upon those already determined above
-    for s in v {
-        let mut ss = s.clone();
-        ss.sort_unstable();
-        match s.len() {
-            5 => {
-                let seg1count = vc[1].iter().filter(|seg| s.contains(seg)).count();
-                if seg1count == 2 {
-                    retval.insert(ss, 3);
-                } else {
-                    let seg4count = vc[4].iter().filter(|seg| s.contains(seg)).count();
-                    if seg4count == 2 {
-                        retval.insert(ss, 2);
-                    } else {
-                        retval.insert(ss, 5);
-                    }
+            symbols.push((symbol.to_string(), mask));
+        }
+        if symbols.is_empty() {
+            return Err("no symbols".to_string());
+        }
+        Ok(Self { segments, symbols })
+    }
+}
+
+// bitmask of a pattern of segment (or wire) names
+fn mask_of(names: &[char], pattern: &str) -> Result<u32, String> {
+    pattern
+        .chars()
+        .try_fold(0, |m, c| match names.iter().position(|n| *n == c) {
+            Some(i) => Ok(m | 1 << i),
+            None => Err(format!("unknown wire {} in {}", c, pattern)),
+        })
+}
+
+// what decoding a note came to
+#[derive(Debug, PartialEq, Eq)]
+enum Decoded {
+    Value(String),
+    Ambiguous(Vec<String>), // the outputs read differently depending on the wiring
+    Inconsistent(String),   // no wiring explains the note
+}
+
+// what is still possible while searching for the wiring
+#[derive(Debug, Clone)]
+struct SolverState {
+    domains: Vec<u32>,           // the segments each wire could drive
+    candidates: Vec<Vec<usize>>, // the symbols each pattern could show
+}
+
+struct Solver<'a> {
+    display: &'a Display,
+    patterns: Vec<u32>, // wire bitmasks of every pattern of the note
+}
+
+impl<'a> Solver<'a> {
+    // narrow the state until nothing changes, false if something is left with no options
+    fn propagate(&self, st: &mut SolverState) -> bool {
+        let all = self.display.all_segments();
+        let num_wires = self.display.segments.len();
+        let SolverState {
+            domains,
+            candidates,
+        } = st;
+        loop {
+            let before = domains.clone();
+
+            for (p, cands) in self.patterns.iter().zip(candidates.iter_mut()) {
+                cands.retain(|s| {
+                    let lit = self.display.symbols[*s].1;
+                    (0..num_wires).all(|w| {
+                        let allowed = if p & 1 << w != 0 { lit } else { all & !lit };
+                        domains[w] & allowed != 0
+                    })
+                });
+                if cands.is_empty() {
+                    return false;
+                }
+
+                for (w, d) in domains.iter_mut().enumerate() {
+                    let allowed = cands.iter().fold(0, |acc, s| {
+                        let lit = self.display.symbols[*s].1;
+                        acc | if p & 1 << w != 0 { lit } else { all & !lit }
+                    });
+                    *d &= allowed;
                 }
             }
-            6 => {
-                let seg1count = vc[1].iter().filter(|seg| s.contains(seg)).count();
-                if seg1count == 1 {
-                    retval.insert(ss, 6);
-                } else {
-                    let seg4count = vc[4].iter().filter(|seg| s.contains(seg)).count();
-                    if seg4count == 3 {
-                        retval.insert(ss, 0);
-                    } else {
-                        retval.insert(ss, 9);
+
+            // a segment settled on one wire is taken
+            for w in 0..num_wires {
+                if domains[w].count_ones() == 1 {
+                    let seg = domains[w];
+                    for (ww, d) in domains.iter_mut().enumerate() {
+                        if ww != w {
+                            *d &= !seg;
+                        }
                     }
                 }
             }
-            _ => {}
+
+            if domains.contains(&0) {
+                return false;
+            }
+            if *domains == before {
+                return true;
+            }
         }
     }
 
-    retval
+    // find up to limit complete wirings, each giving the symbol shown by every pattern
+    fn search(&self, mut st: SolverState, limit: usize, found: &mut Vec<Vec<usize>>) {
+        if found.len() >= limit || !self.propagate(&mut st) {
+            return;
+        }
+
+        // branch on the unsettled wire with the fewest options
+        let next = (0..st.domains.len())
+            .filter(|w| st.domains[*w].count_ones() > 1)
+            .min_by_key(|w| st.domains[*w].count_ones());
+        match next {
+            None => found.push(st.candidates.iter().map(|c| c[0]).collect()),
+            Some(w) => {
+                let mut options = st.domains[w];
+                while options != 0 {
+                    let seg = options & options.wrapping_neg();
+                    options &= !seg;
+                    let mut branch = st.clone();
+                    branch.domains[w] = seg;
+                    self.search(branch, limit, found);
+                }
+            }
+        }
+    }
+}
+
+// decode a note, the patterns come before the outputs
+fn decode_note(display: &Display, patterns: &[&str], outputs: &[&str]) -> Decoded {
+    let masks: Result<Vec<u32>, String> = patterns
+        .iter()
+        .chain(outputs)
+        .map(|p| mask_of(&display.segments, p))
+        .collect();
+    let masks = match masks {
+        Ok(m) => m,
+        Err(e) => return Decoded::Inconsistent(e),
+    };
+
+    let candidates = masks
+        .iter()
+        .map(|m| {
+            (0..display.symbols.len())
+                .filter(|s| display.symbols[*s].1.count_ones() == m.count_ones())
+                .collect()
+        })
+        .collect();
+    let solver = Solver {
+        display,
+        patterns: masks,
+    };
+    let start = SolverState {
+        domains: vec![display.all_segments(); display.segments.len()],
+        candidates,
+    };
+
+    let mut found: Vec<Vec<usize>> = vec![];
+    solver.search(start.clone(), 1, &mut found);
+    let first = match found.pop() {
+        Some(f) => f,
+        None => return Decoded::Inconsistent("no wiring fits the patterns".to_string()),
+    };
+
+    // many wirings read the outputs the same, so rather than listing them look for one showing
+    // something else on each output in turn
+    let read = |shown: &[usize]| -> String {
+        shown[patterns.len()..]
+            .iter()
+            .map(|s| display.symbols[*s].0.as_str())
+            .collect()
+    };
+    let mut readings = vec![read(&first)];
+    for (p, shown) in first.iter().enumerate().skip(patterns.len()) {
+        for s in start.candidates[p].iter().filter(|s| *s != shown) {
+            let mut st = start.clone();
+            st.candidates[p] = vec![*s];
+            solver.search(st, 1, &mut found);
+            if let Some(f) = found.pop() {
+                readings.push(read(&f));
+            }
+        }
+    }
+    readings.sort_unstable();
+    readings.dedup();
+
+    match readings.len() {
+        1 => Decoded::Value(readings.pop().unwrap()),
+        _ => Decoded::Ambiguous(readings),
+    }
+}
+
+// small seeded pseudo random number generator (splitmix64)
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform value in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Fisher-Yates shuffle
+    fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+// write a note for the symbols shown on the outputs of a display wired so segment s is driven
+// by wire wiring[s], the patterns and the wires in each are shuffled
+fn encode_note(display: &Display, wiring: &[usize], outputs: &[usize], rng: &mut Rng) -> String {
+    let mut order: Vec<usize> = (0..display.symbols.len()).collect();
+    rng.shuffle(&mut order);
+
+    let mut scramble = |symbol: usize| -> String {
+        let lit = display.symbols[symbol].1;
+        let mut wires: Vec<char> = (0..display.segments.len())
+            .filter(|s| lit & 1 << s != 0)
+            .map(|s| display.segments[wiring[s]])
+            .collect();
+        rng.shuffle(&mut wires);
+        wires.into_iter().collect()
+    };
+
+    let patterns: Vec<String> = order.into_iter().map(&mut scramble).collect();
+    let outputs: Vec<String> = outputs.iter().map(|s| scramble(*s)).collect();
+    format!("{} | {}", patterns.join(" "), outputs.join(" "))
+}
+
+// a random number of width outputs, as the symbols showing it and how it reads
+fn random_number(display: &Display, width: usize, rng: &mut Rng) -> (Vec<usize>, String) {
+    let shown: Vec<usize> = match display.radix() {
+        Some(r) => {
+            let range = (r as u64).checked_pow(width as u32).unwrap_or(u64::MAX);
+            display
+                .spell(rng.next_u64() % range, width)
+                .expect("number doesn't fit the outputs")
+        }
+        None => (0..width)
+            .map(|_| rng.below(display.symbols.len()))
+            .collect(),
+    };
+    let reading = shown
+        .iter()
+        .map(|s| display.symbols[*s].0.as_str())
+        .collect();
+    (shown, reading)
+}
+
+// make notes from random numbers and wirings, decoding each back if check is set
+fn generate(display: &Display, notes: usize, width: usize, seed: u64, check: bool) {
+    let mut rng = Rng::new(seed);
+    for n in 0..notes {
+        let mut wiring: Vec<usize> = (0..display.segments.len()).collect();
+        rng.shuffle(&mut wiring);
+        let (shown, reading) = random_number(display, width, &mut rng);
+        let note = encode_note(display, &wiring, &shown, &mut rng);
+
+        if !check {
+            println!("{}", note);
+            continue;
+        }
+        let (patterns, outputs) = note.split_once(" | ").unwrap();
+        let patterns: Vec<&str> = patterns.split_whitespace().collect();
+        let outputs: Vec<&str> = outputs.split_whitespace().collect();
+        let decoded = decode_note(display, &patterns, &outputs);
+        if decoded != Decoded::Value(reading.clone()) {
+            panic!(
+                "note {} for {} decoded as {:?}: {}",
+                n + 1,
+                reading,
+                decoded,
+                note
+            );
+        }
+    }
+    if check {
+        println!("{} notes decoded back", notes);
+    }
 }
 
 fn main() -> std::io::Result<()> {
     let reader = std::io::stdin();
     let mut inputstr = String::new();
+    let mut display = Display::seven_segment();
     let mut count = 0; // for part one
     let mut sum = 0; // for part two
+    let mut line_num = 0;
+    let mut generated: Option<(usize, bool)> = None; // how many notes and whether to check them
+    let mut width = 4;
+    let mut seed = 2021;
+
+    for arg in std::env::args().skip(1) {
+        if let Some(path) = arg.strip_prefix("--display=") {
+            let text = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("can't read {}: {}", path, e));
+            display = text
+                .parse()
+                .unwrap_or_else(|e: String| panic!("{}: {}", path, e));
+        } else if let Some(n) = arg.strip_prefix("--generate=") {
+            generated = Some((n.parse().expect("bad note count"), false));
+        } else if let Some(n) = arg.strip_prefix("--round-trip=") {
+            generated = Some((n.parse().expect("bad note count"), true));
+        } else if let Some(k) = arg.strip_prefix("--outputs=") {
+            width = k.parse().expect("bad output count");
+        } else if let Some(sd) = arg.strip_prefix("--seed=") {
+            seed = sd.parse().expect("bad seed");
+        } else {
+            panic!("unknown argument {}", arg);
+        }
+    }
+    if let Some((notes, check)) = generated {
+        generate(&display, notes, width, seed, check);
+        return Ok(());
+    }
+    let radix = display.radix();
+
+    // symbols that can be told apart by the number of segments lit alone
+    let unique_lens: Vec<u32> = display
+        .symbols
+        .iter()
+        .map(|(_, m)| m.count_ones())
+        .filter(|l| {
+            display
+                .symbols
+                .iter()
+                .filter(|(_, m)| m.count_ones() == *l)
+                .count()
+                == 1
+        })
+        .collect();
 
     while reader.read_line(&mut inputstr)? != 0 {
-        let iter = inputstr.trim().split(" | ");
-        let mut collecting_input = true;
-        let mut decoder: HashMap<Vec<char>, u32> = HashMap::new();
-        let mut val = 0;
-
-        for s in iter {
-            let sv = s
-                .split(' ')
-                .map(|s| s.chars().collect::<Vec<char>>())
-                .collect::<Vec<_>>();
-
-            if collecting_input {
-                decoder = decode_inputs(&sv);
-            } else {
-                for out in &sv {
-                    let mut nout = out.clone();
-                    nout.sort_unstable();
-                    let len = out.len();
-                    if len == 2 || len == 3 || len == 4 || len == 7 {
-                        count += 1;
-                    }
-                    val *= 10;
-                    val += decoder.get(&nout).unwrap();
-                }
+        line_num += 1;
+        let input = inputstr.trim();
+        if input.is_empty() {
+            inputstr.clear();
+            continue;
+        }
+
+        let (patterns, outputs) = match input.split_once(" | ") {
+            Some((p, o)) => (
+                p.split_whitespace().collect::<Vec<_>>(),
+                o.split_whitespace().collect::<Vec<_>>(),
+            ),
+            None => {
+                println!("line {}: inconsistent, no | separator", line_num);
+                inputstr.clear();
+                continue;
             }
+        };
 
-            collecting_input = false;
+        count += outputs
+            .iter()
+            .filter(|o| unique_lens.contains(&(o.chars().count() as u32)))
+            .count();
+
+        match decode_note(&display, &patterns, &outputs) {
+            Decoded::Value(v) => match radix {
+                Some(r) => {
+                    sum += u64::from_str_radix(&v, r)
+                        .unwrap_or_else(|_| panic!("line {}: {} overflows u64", line_num, v))
+                }
+                None => println!("line {}: reads {}", line_num, v),
+            },
+            Decoded::Ambiguous(readings) => println!(
+                "line {}: ambiguous, the outputs could read {}",
+                line_num,
+                readings.join(" or ")
+            ),
+            Decoded::Inconsistent(why) => println!("line {}: inconsistent, {}", line_num, why),
         }
 
-        sum += val;
         inputstr.clear();
     }
 
-    println!("aoc8a: {} aoc8b: {}", count, sum);
+    match radix {
+        Some(_) => println!("aoc8a: {} aoc8b: {}", count, sum),
+        None => println!("aoc8a: {}", count),
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "0: abcefg\n1: cf\n2: acdeg\n3: acdfg\n4: bcdf\n5: abdfg\n6: abdefg\n7: acf
+8: abcdefg\n9: abcdfg\nA: abcdef\nb: bdefg\nC: abeg\nd: cdefg\nE: abdeg\nF: abde\n";
+
+    // encode the symbols shown with a wiring and check the note decodes back to them
+    fn round_trip(display: &Display, wiring: &[usize], shown: &[usize], rng: &mut Rng) {
+        let note = encode_note(display, wiring, shown, rng);
+        let (patterns, outputs) = note.split_once(" | ").unwrap();
+        let patterns: Vec<&str> = patterns.split_whitespace().collect();
+        let outputs: Vec<&str> = outputs.split_whitespace().collect();
+        let reading: String = shown
+            .iter()
+            .map(|s| display.symbols[*s].0.as_str())
+            .collect();
+        assert_eq!(
+            decode_note(display, &patterns, &outputs),
+            Decoded::Value(reading),
+            "note {}",
+            note
+        );
+    }
+
+    // every ordering of 0..n, by Heap's algorithm
+    fn permutations(n: usize) -> Vec<Vec<usize>> {
+        let mut perm: Vec<usize> = (0..n).collect();
+        let mut counters = vec![0; n];
+        let mut all = vec![perm.clone()];
+        let mut i = 0;
+        while i < n {
+            if counters[i] < i {
+                let j = if i % 2 == 0 { 0 } else { counters[i] };
+                perm.swap(j, i);
+                all.push(perm.clone());
+                counters[i] += 1;
+                i = 0;
+            } else {
+                counters[i] = 0;
+                i += 1;
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn every_seven_segment_wiring() {
+        let display = Display::seven_segment();
+        let wirings = permutations(display.segments.len());
+        assert_eq!(wirings.len(), 5040);
+
+        let mut rng = Rng::new(2021);
+        for (n, wiring) in wirings.iter().enumerate() {
+            let shown = display.spell(n as u64 % 10000, 4).unwrap();
+            round_trip(&display, wiring, &shown, &mut rng);
+        }
+    }
+
+    #[test]
+    fn seeded_hex_notes() {
+        let display: Display = HEX.parse().unwrap();
+        assert_eq!(display.radix(), Some(16));
+
+        for seed in 0..50 {
+            let mut rng = Rng::new(seed);
+            let mut wiring: Vec<usize> = (0..display.segments.len()).collect();
+            rng.shuffle(&mut wiring);
+            let (shown, reading) = random_number(&display, 6, &mut rng);
+            assert_eq!(
+                u64::from_str_radix(&reading, 16).ok(),
+                Some(shown.iter().fold(0, |v, s| v * 16 + *s as u64))
+            );
+            round_trip(&display, &wiring, &shown, &mut rng);
+        }
+    }
+
+    #[test]
+    fn rejects_indistinguishable_symbols() {
+        let err = "0: abcefg\nA: abcefg\n".parse::<Display>().unwrap_err();
+        assert!(err.contains("can't be told apart"), "{}", err);
+    }
+}
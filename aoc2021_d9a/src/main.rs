@@ -1,98 +1,358 @@
 // AoC 2021 day 9
+//
+// each basin is labeled by a flood fill over a label grid, so every cell is visited once.  A
+// basin is a connected region of cells below height 9 and is described by its lowest point,
+// size, depth (the height of its highest cell above its lowest) and bounding box
+//
+//   aoc2021_d9a [--basins] [--map] [--connectivity=4|8] [--wall=H] [--watershed] < input
+// --basins lists every basin, largest first, and --map draws the floor with each basin in its
+// own color and its lowest point highlighted.  --connectivity=8 makes diagonal cells neighbors
+// too and --wall makes cells of height H and up the walls instead of 9s (10 for no walls)
+//
+// --watershed splits the floor where the water runs rather than at the walls: every cell drains
+// to its lowest lower neighbor, a cell on a flat stretch with a way down drains towards the
+// nearest edge of it where the floor drops, and a flat bottom with no way down is a single low
+// point.  A union-find joins each cell with where it drains, so each basin is everything
+// draining to one low point
 
-// function that returns a Vector of (x, y, val) tuples of the neighbors of the passed coordinate
-fn neighbors(x: usize, y: usize, v: &[Vec<u8>]) -> Vec<(usize, usize, u8)> {
-    let mut retval: Vec<(usize, usize, u8)> = vec![];
-
-    // build list of "manhattan" neighbors
-    let yl = y.saturating_sub(1);
-    let yh = if y == v.len() - 1 { y } else { y + 1 };
-    let xl = x.saturating_sub(1);
-    let xh = if x == v[y].len() - 1 { x } else { x + 1 };
-    for yy in yl..=yh {
-        for xx in xl..=xh {
-            if xx == x || yy == y {
-                if xx == x && yy == y {
-                    continue;
-                } else {
-                    retval.push((xx, yy, v[yy][xx]));
+// the heights of the floor, row by row
+struct Floor {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+    diagonals: bool, // whether diagonal cells are neighbors
+    wall: u8,        // cells this high or higher are walls
+}
+
+// a basin and what it looks like
+#[derive(Debug)]
+struct Basin {
+    low: (usize, usize),
+    size: usize,
+    depth: u8,
+    bbox: ((usize, usize), (usize, usize)), // top left and bottom right corners
+}
+
+// every cell's basin (None for walls) and the basins
+struct BasinMap {
+    labels: Vec<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+impl Floor {
+    fn xy(&self, i: usize) -> (usize, usize) {
+        (i % self.width, i / self.width)
+    }
+
+    // indices of the neighbors of cell i, "manhattan" ones unless diagonals are included
+    fn neighbors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        const OFFSETS: [(isize, isize); 8] = [
+            (0, -1),
+            (-1, 0),
+            (1, 0),
+            (0, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ];
+        let (x, y) = self.xy(i);
+        let count = if self.diagonals { 8 } else { 4 };
+        OFFSETS[..count].iter().filter_map(move |(dx, dy)| {
+            let xx = x.checked_add_signed(*dx)?;
+            let yy = y.checked_add_signed(*dy)?;
+            if xx < self.width && yy < self.height {
+                Some(yy * self.width + xx)
+            } else {
+                None
+            }
+        })
+    }
+
+    // cells lower than all their neighbors
+    fn low_points(&self) -> Vec<usize> {
+        (0..self.cells.len())
+            .filter(|i| self.neighbors(*i).all(|n| self.cells[*i] < self.cells[n]))
+            .collect()
+    }
+
+    fn is_wall(&self, i: usize) -> bool {
+        self.cells[i] >= self.wall
+    }
+
+    // flood fill each basin from the first of its cells found
+    fn label_basins(&self) -> BasinMap {
+        let mut labels: Vec<Option<usize>> = vec![None; self.cells.len()];
+        let mut count = 0;
+        let mut stack: Vec<usize> = vec![];
+
+        for start in 0..self.cells.len() {
+            if self.is_wall(start) || labels[start].is_some() {
+                continue;
+            }
+            labels[start] = Some(count);
+            stack.push(start);
+            while let Some(i) = stack.pop() {
+                for n in self.neighbors(i) {
+                    if !self.is_wall(n) && labels[n].is_none() {
+                        labels[n] = Some(count);
+                        stack.push(n);
+                    }
+                }
+            }
+            count += 1;
+        }
+
+        self.describe(labels, count)
+    }
+
+    // split the floor by where the water drains to
+    fn watershed(&self) -> BasinMap {
+        let n = self.cells.len();
+
+        // steepest descent, ties to the first neighbor in reading order
+        let mut drain: Vec<Option<usize>> = (0..n)
+            .map(|i| {
+                if self.is_wall(i) {
+                    return None;
+                }
+                self.neighbors(i)
+                    .filter(|nb| !self.is_wall(*nb) && self.cells[*nb] < self.cells[i])
+                    .min_by_key(|nb| (self.cells[*nb], *nb))
+            })
+            .collect();
+
+        // flat cells drain towards the nearest cell of the same height that has a way down
+        let mut queue: std::collections::VecDeque<usize> =
+            (0..n).filter(|i| drain[*i].is_some()).collect();
+        while let Some(i) = queue.pop_front() {
+            for nb in self.neighbors(i) {
+                if !self.is_wall(nb) && self.cells[nb] == self.cells[i] && drain[nb].is_none() {
+                    drain[nb] = Some(i);
+                    queue.push_back(nb);
                 }
             }
         }
+
+        // join each cell with where it drains, and the cells of a flat bottom with each other
+        let mut sets = DisjointSets::new(n);
+        for i in (0..n).filter(|i| !self.is_wall(*i)) {
+            match drain[i] {
+                Some(d) => sets.union(i, d),
+                None => {
+                    for nb in self.neighbors(i) {
+                        if !self.is_wall(nb) && drain[nb].is_none() {
+                            sets.union(i, nb);
+                        }
+                    }
+                }
+            }
+        }
+
+        // number the basins in the order they're first met
+        let mut label_of_root: Vec<Option<usize>> = vec![None; n];
+        let mut count = 0;
+        let labels = (0..n)
+            .map(|i| {
+                if self.is_wall(i) {
+                    return None;
+                }
+                let root = sets.find(i);
+                Some(*label_of_root[root].get_or_insert_with(|| {
+                    count += 1;
+                    count - 1
+                }))
+            })
+            .collect();
+
+        self.describe(labels, count)
     }
 
-    retval
+    // describe the count basins of a labeling, numbered in the order they are first met
+    fn describe(&self, labels: Vec<Option<usize>>, count: usize) -> BasinMap {
+        let mut lows: Vec<Option<usize>> = vec![None; count];
+        let mut highest = vec![0; count];
+        let mut basins: Vec<Basin> = vec![];
+
+        for (i, label) in labels.iter().enumerate() {
+            let b = match label {
+                Some(b) => *b,
+                None => continue,
+            };
+            let (x, y) = self.xy(i);
+            if b == basins.len() {
+                basins.push(Basin {
+                    low: (x, y),
+                    size: 0,
+                    depth: 0,
+                    bbox: ((x, y), (x, y)),
+                });
+            }
+            let basin = &mut basins[b];
+            basin.size += 1;
+            let ((x0, y0), (x1, y1)) = basin.bbox;
+            basin.bbox = ((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y)));
+            highest[b] = highest[b].max(self.cells[i]);
+            // ties go to the first cell in reading order
+            if lows[b].is_none_or(|l| self.cells[i] < self.cells[l]) {
+                lows[b] = Some(i);
+            }
+        }
+
+        for (b, basin) in basins.iter_mut().enumerate() {
+            let low = lows[b].unwrap();
+            basin.low = self.xy(low);
+            basin.depth = highest[b] - self.cells[low];
+        }
+
+        BasinMap { labels, basins }
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    let reader = std::io::stdin();
-    let mut inputstr = String::new();
-    let mut floor: Vec<Vec<u8>> = vec![];
+// union-find over the cells, with path halving and union by size
+struct DisjointSets {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
 
-    while reader.read_line(&mut inputstr)? != 0 {
-        let mut row: Vec<u8> = vec![];
-        for c in inputstr.trim().chars() {
-            row.push(c as u8 - b'0');
+impl DisjointSets {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+            size: vec![1; n],
         }
-        floor.push(row);
+    }
 
-        inputstr.clear();
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
     }
 
-    let mut basin_vec: Vec<Vec<(usize, usize)>> = vec![]; // for part two
-
-    // part one - find low points and score their risk
-    let mut risk_sum = 0_u32;
-    for y in 0..floor.len() {
-        for x in 0..floor[y].len() {
-            let mut tot = 0;
-            let mut lower = 0;
-            for (_, _, val) in neighbors(x, y, &floor) {
-                tot += 1;
-                if floor[y][x] < val {
-                    lower += 1;
-                } else {
-                    break;
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+// draw the floor with a background color per basin and the lowest points in bold
+fn render(floor: &Floor, map: &BasinMap) -> String {
+    const COLORS: [u8; 12] = [41, 42, 43, 44, 45, 46, 101, 102, 103, 104, 105, 106];
+    let mut out = String::new();
+    for y in 0..floor.height {
+        for x in 0..floor.width {
+            let i = y * floor.width + x;
+            let digit = (b'0' + floor.cells[i]) as char;
+            match map.labels[i] {
+                None => out.push(digit),
+                Some(l) => {
+                    let bold = if map.basins[l].low == (x, y) {
+                        "1;"
+                    } else {
+                        ""
+                    };
+                    out += &format!(
+                        "\x1b[{}30;{}m{}\x1b[0m",
+                        bold,
+                        COLORS[l % COLORS.len()],
+                        digit
+                    );
                 }
             }
-            if lower == tot {
-                risk_sum += floor[y][x] as u32 + 1;
+        }
+        out.push('\n');
+    }
+    out
+}
 
-                // prepare for part two
-                basin_vec.push(vec![(x, y)]);
-            }
+fn main() -> std::io::Result<()> {
+    let reader = std::io::stdin();
+    let mut inputstr = String::new();
+    let mut floor = Floor {
+        width: 0,
+        height: 0,
+        cells: vec![],
+        diagonals: false,
+        wall: 9,
+    };
+    let mut list_basins = false;
+    let mut draw_map = false;
+    let mut watershed = false;
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--basins" => list_basins = true,
+            "--map" => draw_map = true,
+            "--watershed" => watershed = true,
+            "--connectivity=4" => floor.diagonals = false,
+            "--connectivity=8" => floor.diagonals = true,
+            _ => match arg.strip_prefix("--wall=") {
+                Some(h) => floor.wall = h.parse().expect("bad wall height"),
+                None => panic!("unknown argument {}", arg),
+            },
         }
     }
 
-    // part two, basin_size is a vector of the size of each basin
-    let mut basin_size: Vec<usize> = vec![];
-
-    // basin_vec contains a vector of the lowest point of each basin
-    for bv in basin_vec.iter_mut() {
-        let mut basin: Vec<(usize, usize)> = vec![];
-
-        // DFS to build each entire basin in basin
-        while !bv.is_empty() {
-            let (x, y) = bv.pop().unwrap();
-            if !basin.contains(&(x, y)) {
-                basin.push((x, y));
-                for (xx, yy, val) in neighbors(x, y, &floor) {
-                    if val < 9 && !basin.contains(&(xx, yy)) {
-                        bv.push((xx, yy));
-                    }
-                }
+    while reader.read_line(&mut inputstr)? != 0 {
+        let input = inputstr.trim();
+        if !input.is_empty() {
+            let row: Vec<u8> = input
+                .chars()
+                .map(|c| c.to_digit(10).expect("bad height") as u8)
+                .collect();
+            if floor.height == 0 {
+                floor.width = row.len();
+            } else if row.len() != floor.width {
+                panic!("row {} isn't {} wide", floor.height + 1, floor.width);
             }
+            floor.cells.extend(row);
+            floor.height += 1;
         }
-        basin_size.push(basin.len());
+
+        inputstr.clear();
     }
 
-    basin_size.sort_unstable();
-    basin_size.reverse();
-    println!(
-        "aoc9a: {} aoc9b: {}",
-        risk_sum,
-        basin_size[0] * basin_size[1] * basin_size[2]
-    );
+    // part one - score the risk of the low points
+    let risk_sum: u32 = floor
+        .low_points()
+        .iter()
+        .map(|i| floor.cells[*i] as u32 + 1)
+        .sum();
+
+    // part two - multiply the sizes of the three largest basins
+    let map = if watershed {
+        floor.watershed()
+    } else {
+        floor.label_basins()
+    };
+    let mut order: Vec<usize> = (0..map.basins.len()).collect();
+    order.sort_by_key(|b| std::cmp::Reverse(map.basins[*b].size));
+    let product: usize = order.iter().take(3).map(|b| map.basins[*b].size).product();
+
+    println!("aoc9a: {} aoc9b: {}", risk_sum, product);
+
+    if list_basins {
+        for b in &order {
+            let basin = &map.basins[*b];
+            let ((x0, y0), (x1, y1)) = basin.bbox;
+            println!(
+                "basin {}: low point {},{} size {} depth {} box {},{}-{},{}",
+                b, basin.low.0, basin.low.1, basin.size, basin.depth, x0, y0, x1, y1
+            );
+        }
+    }
+    if draw_map {
+        print!("{}", render(&floor, &map));
+    }
 
     Ok(())
 }